@@ -0,0 +1,216 @@
+#![allow(clippy::needless_return)]
+extern crate chrono;
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate iron;
+extern crate router;
+
+mod plib;
+
+use clap::{ArgMatches, Arg, App};
+
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+use iron::{
+    prelude::*,
+    status,
+    modifiers::Header,
+    headers::ContentType,
+    mime::{Mime, TopLevel, SubLevel},
+};
+use router::Router;
+use plib::{
+    config::{self, PiConfig},
+    metrics::{self, Scrape},
+    pihole::Pihole,
+};
+
+static LOGGER: GlobalLogger = GlobalLogger;
+struct GlobalLogger;
+
+/// This implements the logging to stderr from the \`log\` crate
+impl log::Log for GlobalLogger {
+    fn enabled(&self, meta: &log::Metadata) -> bool {
+        return meta.level() <= log::max_level();
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let d = chrono::Local::now();
+            eprintln!(
+                "{} - {} - {}:{} {} - {}",
+                d.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                record.level(),
+                record.file().unwrap(),
+                record.line().unwrap(),
+                record.target(),
+                record.args(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Create a set of CLI args via the \`clap\` crate and return the matches
+fn get_args() -> ArgMatches<'static> {
+    let matches = App::new("pi-ctl-exporter")
+        .version(crate_version!())
+        .author("Jay Deiman")
+        .about("Export the stats from your Pi-hole servers as Prometheus \
+            metrics")
+        .set_term_width(80)
+        .arg_from_usage("-D, --debug 'Turn on debug output'")
+        .arg(Arg::with_name("pi_list_config")
+            .short("-l")
+            .long("--pi-list-config")
+            .default_value("/etc/.mpihole")
+            .help("The path to the JSON config created by running `pi-ctl -r` \
+                This is the list of servers to scrape")
+        )
+        .arg(Arg::with_name("bind_to")
+            .short("-b")
+            .long("--bind-to")
+            .value_name("ADDR")
+            .default_value("0.0.0.0:9617")
+            .help("The address and port to serve the metrics on")
+        )
+        .arg(Arg::with_name("interval")
+            .short("-i")
+            .long("--interval")
+            .value_name("SECS")
+            .default_value("30")
+            .help("How often to scrape the servers.  Requests for /metrics \
+                in between get the cached results")
+        )
+        .arg(Arg::with_name("top_clients")
+            .short("-n")
+            .long("--top-clients")
+            .value_name("INT")
+            .default_value("10")
+            .help("Export the query counts for this many top clients per \
+                server")
+        )
+        .get_matches();
+
+    return matches;
+}
+
+/// Set the global logger from the \`log\` crate
+fn setup_logging(args: &ArgMatches) {
+    let l = if args.is_present("debug") {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(l);
+}
+
+/// Scrape all the servers at once and render the metrics.  Servers without
+/// a session, or that failed, are (re)authenticated first
+fn scrape(servers: &mut [Pihole], top_clients: usize) -> String {
+    let start = Instant::now();
+    let scrapes: Vec<Scrape> = thread::scope(|scope| {
+        let handles: Vec<_> = servers.iter_mut()
+            .map(|s| scope.spawn(move || {
+                if s.sid.is_none() && s.auth().is_none() {
+                    warn!("Failed to authenticate with server: {}",
+                        s.base_url);
+                }
+                let ret = Scrape::fetch(s, top_clients);
+                if !ret.is_up() {
+                    // Get a new session on the next scrape, ending this one
+                    // so failed scrapes don't use up the server's sessions
+                    s.logout();
+                }
+                ret
+            }))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap_or_default()).collect()
+    });
+
+    let mut reg = metrics::build(&scrapes);
+    reg.add("pi_ctl_exporter_scrape_duration_seconds",
+        "How long the last scrape of all the servers took", &[],
+        start.elapsed().as_secs_f64());
+    reg.add("pi_ctl_exporter_last_scrape_timestamp_seconds",
+        "When the servers were last scraped", &[],
+        chrono::Utc::now().timestamp() as f64);
+
+    return reg.render();
+}
+
+fn metrics_route(_: &mut Request, cache: Arc<RwLock<String>>) -> IronResult<Response> {
+    let body = cache.read().unwrap().clone();
+    let content_type = Header(ContentType(
+        "text/plain; version=0.0.4; charset=utf-8".parse::<Mime>().unwrap()
+    ));
+
+    return Ok(Response::with((status::Ok, body, content_type)));
+}
+
+fn index(_: &mut Request) -> IronResult<Response> {
+    let content = "<html><head><title>pi-ctl exporter</title></head>\
+        <body><a href=\"/metrics\">Metrics</a></body></html>\n";
+    let content_type = Header(
+        ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![]))
+    );
+
+    return Ok(Response::with((status::Ok, content, content_type)));
+}
+
+fn main() {
+    let args = get_args();
+    setup_logging(&args);
+
+    let conf_path = Path::new(args.value_of("pi_list_config").unwrap());
+    let server_conf = match PiConfig::from_path(conf_path) {
+        Ok(c) => c,
+        Err(config::FromPath::SerError(e))=> {
+            error!("Failed to deserialize config: {}", e);
+            std::process::exit(1);
+        },
+        _ => {
+            error!("Unknown config error");
+            std::process::exit(1);
+        }
+    };
+    let interval = value_t!(args, "interval", u64)
+        .unwrap_or_else(|e| e.exit()).max(1);
+    let top_clients = value_t!(args, "top_clients", usize)
+        .unwrap_or_else(|e| e.exit());
+
+    let mut servers: Vec<Pihole> = server_conf.servers
+        .iter()
+        .map(Pihole::from_cfg)
+        .collect();
+
+    // Scrape once up front so the first request has metrics
+    let cache = Arc::new(RwLock::new(scrape(&mut servers, top_clients)));
+    let scrape_cache = cache.clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval));
+        let body = scrape(&mut servers, top_clients);
+        *scrape_cache.write().unwrap() = body;
+    });
+
+    let mut router = Router::new();
+    router.get(
+        "/metrics",
+        move |r: &mut Request| metrics_route(r, cache.clone()),
+        "metrics",
+    );
+    router.get("/", index, "index");
+
+    let bind_to = args.value_of("bind_to").unwrap();
+    debug!("Creating metrics server bound to {}", bind_to);
+    Iron::new(router).http(bind_to).unwrap();
+}
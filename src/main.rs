@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+#![allow(clippy::needless_return)]
 extern crate chrono;
 #[macro_use]
 extern crate clap;
@@ -9,16 +10,37 @@ extern crate serde_json;
 
 mod plib;
 
-use clap::{ArgMatches, App, Arg, SubCommand, value_t};
+use chrono::TimeZone;
+use clap::{ArgMatches, App, AppSettings, Arg, SubCommand, value_t, values_t};
 use std::{
     path::Path,
     env,
-    fs::File,
+    fs::{self, File},
     io::{self, Read, Write},
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
+use serde_json::Value;
 use plib::{
+    aggregate,
+    backup,
+    check,
+    color::{self, Color},
     config::{self, PiServer, PiConfig},
+    dhcp,
+    ftl_config,
+    health,
+    logs,
+    messages,
+    network,
+    output::{self, Format},
     pihole::Pihole,
+    push,
+    tables,
+    top,
+    upstream,
+    version::{self, Versions},
 };
 
 static LOGGER: GlobalLogger = GlobalLogger;
@@ -49,6 +71,37 @@ impl log::Log for GlobalLogger {
     fn flush(&self) {}
 }
 
+/// The flag for the stats subcommands to merge the results from all servers
+fn aggregate_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("aggregate")
+        .short("-a")
+        .long("--aggregate")
+        .help("Merge the results from all servers into fleet totals, \
+            showing each server's contribution");
+}
+
+/// The args shared by the `upstreams` subcommands that change the servers
+fn upstream_change_cmd<'a, 'b>(name: &str) -> App<'a, 'b> {
+    return SubCommand::with_name(name)
+        .arg(Arg::with_name("upstream")
+            .value_name("IP[#PORT]")
+            .required(true)
+            .multiple(true)
+            .help("The upstream servers, ex: 1.1.1.1 or 127.0.0.1#5335")
+        )
+        .arg(Arg::with_name("check")
+            .long("--check")
+            .help("Resolve a test name against each new upstream from this \
+                machine before changing anything")
+        )
+        .arg(Arg::with_name("test_name")
+            .long("--test-name")
+            .value_name("NAME")
+            .default_value("pi-hole.net")
+            .help("The name to resolve with --check")
+        );
+}
+
 /// Create a set of CLI args via the `clap` crate and return the matches
 fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
     let matches = App::new(crate_name!())
@@ -75,9 +128,15 @@ fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
         )
         .subcommand(SubCommand::with_name("summary")
             .about("Print a summary for each server")
+            .arg(aggregate_arg())
         )
         .subcommand(SubCommand::with_name("version")
             .about("Print the version for each server")
+            .arg(Arg::with_name("outdated")
+                .long("--outdated")
+                .help("Print the installed and available versions of each \
+                    component, and exit non-zero if any server is behind")
+            )
         )
         .subcommand(SubCommand::with_name("top-domains")
             .about("Print the top N domains")
@@ -88,6 +147,7 @@ fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
                 .default_value("10")
                 .help("Print this many domains")
             )
+            .arg(aggregate_arg())
         )
         .subcommand(SubCommand::with_name("top-clients")
             .about("Print the query data for the top N clients")
@@ -98,12 +158,30 @@ fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
                 .default_value("10")
                 .help("Print this many clients")
             )
+            .arg(aggregate_arg())
         )
         .subcommand(SubCommand::with_name("upstreams")
-            .about("Print the forward destination stats")
+            .about("Print the forward destination stats, or change the \
+                upstream DNS servers")
+            .arg(aggregate_arg())
+            .subcommand(upstream_change_cmd("set")
+                .about("Replace the upstream DNS servers")
+            )
+            .subcommand(upstream_change_cmd("add")
+                .about("Add upstream DNS servers")
+            )
+            .subcommand(upstream_change_cmd("remove")
+                .about("Remove upstream DNS servers")
+                .arg(Arg::with_name("force")
+                    .long("--force")
+                    .help("Remove the upstreams even if it leaves a server \
+                        with none")
+                )
+            )
         )
         .subcommand(SubCommand::with_name("query-types")
             .about("Print the query type stats")
+            .arg(aggregate_arg())
         )
         .subcommand(SubCommand::with_name("recent-blocked")
             .about("Print the most recently blocked domain")
@@ -115,6 +193,339 @@ fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
                 .help("Print this many most recent blocked domains")
             )
         )
+        .subcommand(SubCommand::with_name("backup")
+            .about("Save a teleporter backup of each server into a directory")
+            .arg(Arg::with_name("dir")
+                .short("-d")
+                .long("--dir")
+                .value_name("PATH")
+                .required(true)
+                .help("The directory to write the backup archives to")
+            )
+            .arg(Arg::with_name("keep")
+                .short("-k")
+                .long("--keep")
+                .value_name("INT")
+                .default_value("7")
+                .help("Keep this many backups per server, removing the \
+                    oldest.  Use 0 to keep all of them")
+            )
+        )
+        .subcommand(SubCommand::with_name("restore")
+            .about("Restore a teleporter backup to one or all servers")
+            .arg(Arg::with_name("from")
+                .short("-f")
+                .long("--from")
+                .value_name("ZIP")
+                .required(true)
+                .help("The teleporter archive to restore")
+            )
+            .arg(Arg::with_name("to")
+                .short("-t")
+                .long("--to")
+                .value_name("SERVER")
+                .required(true)
+                .help("The server url or host to restore to, or \"all\"")
+            )
+            .arg(Arg::with_name("only")
+                .short("-o")
+                .long("--only")
+                .value_name("CATEGORY")
+                .multiple(true)
+                .use_delimiter(true)
+                .possible_values(backup::IMPORT_CATEGORIES)
+                .help("Only import these categories.  The default is to \
+                    import everything in the archive")
+            )
+        )
+        .subcommand(SubCommand::with_name("config")
+            .about("Get, set or compare Pi-hole settings on all servers")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("get")
+                .about("Print a setting, or section of settings, by its \
+                    dotted path (ex: dns.upstreams)")
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("The dotted path of the setting")
+                )
+            )
+            .subcommand(SubCommand::with_name("set")
+                .about("Set a setting on all servers.  The value must have \
+                    the same type as the current value")
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("The dotted path of the setting")
+                )
+                .arg(Arg::with_name("value")
+                    .required(true)
+                    .help("The new value as JSON.  Strings don't need to \
+                        be quoted")
+                )
+            )
+            .subcommand(SubCommand::with_name("diff")
+                .about("Compare a setting across all servers, exiting \
+                    non-zero if they differ")
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("The dotted path of the setting")
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("dhcp")
+            .about("Manage the DHCP leases on the servers")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("leases")
+                .about("Print the leases from all servers, flagging IPs or \
+                    MACs that are assigned by more than one server")
+                .arg(Arg::with_name("hosts")
+                    .long("--hosts")
+                    .help("Print the named leases in /etc/hosts format")
+                )
+            )
+            .subcommand(SubCommand::with_name("delete")
+                .about("Delete the lease for an IP")
+                .arg(Arg::with_name("ip")
+                    .required(true)
+                    .help("The IP of the lease to delete")
+                )
+                .arg(Arg::with_name("server")
+                    .long("--server")
+                    .value_name("SERVER")
+                    .default_value("all")
+                    .help("The server url or host to delete the lease on")
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("network")
+            .about("Show the network devices, gateway and interfaces")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("devices")
+                .about("Print the devices seen by all servers, merged by MAC")
+                .arg(Arg::with_name("stale_days")
+                    .long("--stale-days")
+                    .value_name("DAYS")
+                    .help("Only show devices not seen by any server for \
+                        this many days")
+                )
+                .arg(Arg::with_name("delete")
+                    .long("--delete")
+                    .requires("stale_days")
+                    .help("Delete the stale devices from every server")
+                )
+            )
+            .subcommand(SubCommand::with_name("gateway")
+                .about("Print the gateway for each server")
+            )
+            .subcommand(SubCommand::with_name("interfaces")
+                .about("Print the network interfaces for each server")
+            )
+        )
+        .subcommand(SubCommand::with_name("health")
+            .about("Print a health table for the servers.  This exits with \
+                1 if any server is over a threshold, or 2 if any server is \
+                unreachable")
+            .arg(Arg::with_name("max_load")
+                .long("--max-load")
+                .value_name("FLOAT")
+                .default_value("1.0")
+                .help("Warn when the 1 minute load per CPU is over this")
+            )
+            .arg(Arg::with_name("max_mem")
+                .long("--max-mem")
+                .value_name("PERCENT")
+                .default_value("90")
+                .help("Warn when the RAM usage is over this percentage")
+            )
+            .arg(Arg::with_name("max_temp")
+                .long("--max-temp")
+                .value_name("DEGREES")
+                .default_value("70")
+                .help("Warn when the CPU temperature is over this")
+            )
+            .arg(Arg::with_name("max_gravity_age")
+                .long("--max-gravity-age")
+                .value_name("DAYS")
+                .default_value("7")
+                .help("Warn when gravity hasn't been updated in this many \
+                    days")
+            )
+            .arg(Arg::with_name("no_color")
+                .long("--no-color")
+                .help("Don't color the rows")
+            )
+        )
+        .subcommand(SubCommand::with_name("check")
+            .about("A Nagios/Icinga check of all servers.  This prints one \
+                status line with perfdata and exits with 0 (OK), 1 \
+                (WARNING), 2 (CRITICAL) or 3 (UNKNOWN)")
+            .arg(Arg::with_name("checks")
+                .long("--checks")
+                .value_name("LIST")
+                .possible_values(check::CHECKS)
+                .use_delimiter(true)
+                .default_value("reachable,blocking,gravity,version,queries,\
+                    messages")
+                .help("A comma separated list of the conditions to check")
+            )
+            .arg(Arg::with_name("gravity_days")
+                .long("--gravity-days")
+                .value_name("DAYS")
+                .default_value("7")
+                .help("Warn when gravity hasn't been updated in this many \
+                    days")
+            )
+            .arg(Arg::with_name("window")
+                .long("--window")
+                .value_name("MINS")
+                .default_value("5")
+                .help("The number of minutes to count the recent queries \
+                    over")
+            )
+            .arg(Arg::with_name("min_queries")
+                .long("--min-queries")
+                .value_name("INT")
+                .default_value("1")
+                .help("Critical when a server got fewer queries than this \
+                    in the window")
+            )
+        )
+        .subcommand(SubCommand::with_name("messages")
+            .about("Print the FTL diagnosis messages from all servers.  \
+                Nothing is printed if there are none, which suits cron")
+            .arg(Arg::with_name("since")
+                .long("--since")
+                .value_name("WHEN")
+                .help("Only show messages newer than this, either a unix \
+                    timestamp or a duration like 30m, 12h or 2d")
+            )
+            .subcommand(SubCommand::with_name("delete")
+                .about("Delete (acknowledge) messages on a server by ID")
+                .arg(Arg::with_name("server")
+                    .long("--server")
+                    .value_name("SERVER")
+                    .required(true)
+                    .help("The server url or host the IDs belong to")
+                )
+                .arg(Arg::with_name("id")
+                    .value_name("ID")
+                    .required(true)
+                    .multiple(true)
+                    .help("The message IDs to delete")
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("push-metrics")
+            .about("Push the summary and query type stats for each server \
+                to InfluxDB or Graphite, once or on an interval")
+            .arg(Arg::with_name("to")
+                .long("--to")
+                .value_name("URL")
+                .required(true)
+                .help("Where to send the metrics, a udp://host:port, \
+                    tcp://host:port or http(s):// url, ex: \
+                    http://influx:8086/write?db=pihole")
+            )
+            .arg(Arg::with_name("format")
+                .short("-f")
+                .long("--format")
+                .value_name("PROTO")
+                .possible_values(push::PROTOCOLS)
+                .default_value("influx")
+                .help("InfluxDB line protocol or Graphite plaintext")
+            )
+            .arg(Arg::with_name("prefix")
+                .long("--prefix")
+                .value_name("PREFIX")
+                .default_value("pihole")
+                .help("The prefix for the measurement names (InfluxDB) or \
+                    metric paths (Graphite)")
+            )
+            .arg(Arg::with_name("header")
+                .short("-H")
+                .long("--header")
+                .value_name("NAME: VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Add a header to HTTP pushes, ex: \
+                    'Authorization: Token abc'.  Can be repeated")
+            )
+            .arg(Arg::with_name("interval")
+                .short("-i")
+                .long("--interval")
+                .value_name("SECS")
+                .help("Keep pushing the metrics this often.  Without this, \
+                    they are pushed once, which suits cron")
+            )
+        )
+        .subcommand(SubCommand::with_name("top")
+            .about("A live dashboard of all servers that refreshes until \
+                you quit")
+            .arg(Arg::with_name("interval")
+                .short("-i")
+                .long("--interval")
+                .value_name("SECS")
+                .default_value("2")
+                .help("How often to refresh the stats")
+            )
+            .arg(Arg::with_name("time")
+                .short("-t")
+                .long("--time")
+                .value_name("INT")
+                .default_value("300")
+                .help("The time, in seconds, to disable blocking for with \
+                    the 'd' key")
+            )
+            .arg(Arg::with_name("recent")
+                .short("-r")
+                .long("--recent")
+                .value_name("INT")
+                .default_value("5")
+                .help("Show this many recently blocked domains per server")
+            )
+            .arg(Arg::with_name("clients")
+                .short("-n")
+                .long("--clients")
+                .value_name("INT")
+                .default_value("5")
+                .help("Show this many top clients per server")
+            )
+        )
+        .subcommand(SubCommand::with_name("logs")
+            .about("Print the logs from all servers, prefixed with the \
+                server name")
+            .arg(Arg::with_name("log")
+                .short("-l")
+                .long("--log")
+                .value_name("NAME")
+                .possible_values(logs::LOG_NAMES)
+                .default_value("dnsmasq")
+                .help("The log to read")
+            )
+            .arg(Arg::with_name("follow")
+                .short("-f")
+                .long("--follow")
+                .help("Keep following the logs on all servers")
+            )
+            .arg(Arg::with_name("lines")
+                .short("-n")
+                .long("--lines")
+                .value_name("INT")
+                .default_value("10")
+                .help("Print this many of the most recent lines per server \
+                    first")
+            )
+            .arg(Arg::with_name("interval")
+                .short("-i")
+                .long("--interval")
+                .value_name("SECS")
+                .default_value("2")
+                .help("How often to poll the servers when following")
+            )
+            .arg(Arg::with_name("no_color")
+                .long("--no-color")
+                .help("Don't color the server names")
+            )
+        )
         .arg(Arg::with_name("config")
             .short("-c")
             .long("--config")
@@ -122,6 +533,16 @@ fn get_args<'a>(def_conf: &'a str) -> ArgMatches<'a> {
             .value_name("PATH")
             .help("The path to the config file")
         )
+        .arg(Arg::with_name("output")
+            .short("-O")
+            .long("--output")
+            .value_name("FORMAT")
+            .possible_values(output::FORMATS)
+            .default_value("table")
+            .global(true)
+            .help("The output format.  The machine readable formats combine \
+                the results from all servers into one document")
+        )
         .arg_from_usage("-s, --show-config 'Show the current config and exit'")
         .arg_from_usage("-r, --reconfigure '(Re)configure your pihole servers'")
         .arg_from_usage("-D, --debug 'Turn on debug output'")
@@ -149,11 +570,7 @@ fn check_yes(ans: &str) -> bool {
 }
 
 fn check_num_resp(ans: &str) -> Option<usize> {
-    let _ans = ans.trim();
-    return match _ans.parse::<usize>() {
-        Ok(n) => Some(n),
-        _ => None,
-    };
+    return ans.trim().parse::<usize>().ok();
 }
 
 /// Will return True if user wants to add another, false otherwise
@@ -220,7 +637,7 @@ fn configure(conf_path: &Path, cur_config: Option<config::PiConfig>) -> config::
         ret = c.clone();
     }
 
-    if ret.servers.len() < 1 {
+    if ret.servers.is_empty() {
         // This is a new configuration
         println!("Welcome to the mpihole configuration!\n");
         println!("We're going to configure some new pihole servers.  For each");
@@ -270,15 +687,1045 @@ fn configure(conf_path: &Path, cur_config: Option<config::PiConfig>) -> config::
 
 fn show_config(conf_path: &Path) {
     let mut c = String::new();
-    let mut fp = File::open(conf_path).expect(
-        &format!("Could not open conf file at: {}", conf_path.to_string_lossy())
-    );
+    let mut fp = File::open(conf_path).unwrap_or_else(|e| {
+        panic!("Could not open conf file at: {}: {}",
+            conf_path.to_string_lossy(), e)
+    });
 
     fp.read_to_string(&mut c).ok();
 
     println!("{}", c);
 }
 
+/// Format a unix timestamp in local time, using `zero` for a 0 timestamp
+fn fmt_ts(ts: i64, zero: &str) -> String {
+    return match chrono::Local.timestamp_opt(ts, 0) {
+        chrono::LocalResult::Single(t) if ts > 0 =>
+            t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => zero.to_string(),
+    };
+}
+
+/// Run a call against every server and collect the results keyed by url,
+/// warning about any server that failed
+fn collect<F>(servers: &[Pihole], what: &str, f: F) -> output::Results
+where
+    F: Fn(&Pihole) -> Option<Value>,
+{
+    return servers
+        .iter()
+        .map(|s| {
+            let v = f(s);
+            if v.is_none() {
+                warn!("Couldn't get {} for {}", what, s.base_url);
+            }
+            (s.base_url.clone(), v)
+        })
+        .collect();
+}
+
+/// Print the results in the chosen output format.  If there is no table
+/// for the table format, each server's JSON is printed under a header
+fn print_results(
+    fmt: Format,
+    results: &output::Results,
+    table: Option<&output::Table>,
+    header: &str,
+) {
+    if let Some(out) = output::render(fmt, results, table) {
+        print!("{}", out);
+        return;
+    }
+
+    for (server, val) in results {
+        if let Some(v) = val {
+            println!("{} {}", header, server);
+            println!("{}", serde_json::to_string_pretty(v).ok().unwrap());
+            println!();
+        }
+    }
+}
+
+/// Print the merged fleet results.  The machine readable formats get the
+/// merged result under "fleet" along with each server's own result
+fn print_fleet(
+    fmt: Format,
+    results: &output::Results,
+    agg: Value,
+    table: &output::Table,
+) {
+    let mut all: output::Results = vec![("fleet".to_string(), Some(agg))];
+    all.extend(results.iter().cloned());
+
+    print!("{}", output::render(fmt, &all, Some(table)).unwrap());
+}
+
+/// A fleet-wide top N is re-ranked from each server's own top list, so ask
+/// each server for more than N to catch items that rank lower everywhere
+fn fleet_fetch_count(top_n: usize) -> usize {
+    return (top_n * 2).max(top_n + 10);
+}
+
+fn server_urls(servers: &[Pihole]) -> Vec<String> {
+    return servers.iter().map(|s| s.base_url.clone()).collect();
+}
+
+/// Return the servers matching a url or host, or all of them for "all"
+fn select_servers<'a>(servers: &'a [Pihole], target: &str) -> Vec<&'a Pihole> {
+    if target == "all" {
+        return servers.iter().collect();
+    }
+
+    let t = target.trim_matches('/');
+    return servers
+        .iter()
+        .filter(|s| {
+            s.base_url == t
+                || backup::server_slug(&s.base_url) == backup::server_slug(t)
+                || s.name() == t
+        })
+        .collect();
+}
+
+/// Write a teleporter archive for each server and prune the old ones.
+/// Returns false if any of the servers failed
+fn run_backup(servers: &[Pihole], matches: &ArgMatches) -> bool {
+    let dir = Path::new(matches.value_of("dir").unwrap());
+    let keep = value_t!(matches, "keep", usize).unwrap_or_else(|e| e.exit());
+    let mut ok = true;
+
+    if let Err(e) = fs::create_dir_all(dir) {
+        error!("Failed to create backup dir {}: {}", dir.display(), e);
+        return false;
+    }
+
+    for s in servers {
+        let slug = backup::server_slug(&s.base_url);
+        let zip = match s.teleporter_export() {
+            Some(z) => z,
+            None => {
+                warn!("Couldn't get a teleporter backup for {}", s.base_url);
+                ok = false;
+                continue;
+            },
+        };
+
+        let path = dir.join(
+            backup::backup_filename(&slug, &chrono::Local::now()));
+        if let Err(e) = fs::write(&path, &zip) {
+            error!("Failed to write backup {}: {}", path.display(), e);
+            ok = false;
+            continue;
+        }
+        println!("Saved backup for {} to {}", s.base_url, path.display());
+
+        if keep > 0 {
+            match backup::prune_backups(dir, &slug, keep) {
+                Ok(removed) => {
+                    for r in removed {
+                        debug!("Removed old backup {}", r.display());
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to prune backups for {}: {}",
+                        s.base_url, e);
+                    ok = false;
+                },
+            }
+        }
+    }
+
+    return ok;
+}
+
+/// Import a teleporter archive into the selected servers.  Returns false
+/// if any of the servers failed
+fn run_restore(servers: &[Pihole], matches: &ArgMatches) -> bool {
+    let from = matches.value_of("from").unwrap();
+    let to = matches.value_of("to").unwrap();
+    let only: Vec<&str> = matches.values_of("only")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+
+    let zip = match fs::read(from) {
+        Ok(z) => z,
+        Err(e) => {
+            error!("Failed to read the archive {}: {}", from, e);
+            return false;
+        },
+    };
+
+    let targets = select_servers(servers, to);
+    if targets.is_empty() {
+        error!("No configured server matches '{}'", to);
+        return false;
+    }
+
+    let import = backup::import_selector(&only);
+    let mut ok = true;
+    for s in targets {
+        info!("Restoring {} to {}", from, s.base_url);
+        match s.teleporter_import(&zip, Some(import.clone())) {
+            Some(v) if v.get("error").is_none() => {
+                println!("Restored {} to {}", from, s.base_url);
+                if let Some(files) = v["files"].as_array() {
+                    for f in files {
+                        debug!("  imported: {}", f);
+                    }
+                }
+            },
+            Some(v) => {
+                error!("Failed to restore to {}: {}", s.base_url,
+                    v["error"]["message"]);
+                ok = false;
+            },
+            None => {
+                error!("Failed to restore to {}", s.base_url);
+                ok = false;
+            },
+        }
+    }
+
+    return ok;
+}
+
+/// Return the current value of a config setting on a server
+fn get_setting(s: &Pihole, key: &str, detailed: bool) -> Option<Value> {
+    let v = s.get_config(Some(key), detailed)?;
+    if let Some(e) = v.get("error") {
+        warn!("Couldn't get {} from {}: {}", key, s.base_url, e["message"]);
+        return None;
+    }
+
+    return ftl_config::lookup(&v["config"], key).cloned();
+}
+
+fn run_config_get(servers: &[Pihole], key: &str, fmt: Format) -> bool {
+    let res = collect(servers, key, |s| get_setting(s, key, false));
+    let ok = res.iter().all(|(_, v)| v.is_some());
+
+    if fmt != Format::Table {
+        print!("{}", output::render(fmt, &res, None).unwrap());
+        return ok;
+    }
+
+    for (server, val) in res {
+        match val {
+            None => {},
+            Some(v @ Value::Object(_)) | Some(v @ Value::Array(_)) => {
+                println!("{}:", server);
+                println!("{}", serde_json::to_string_pretty(&v).unwrap());
+            },
+            Some(v) => println!("{}: {}", server, ftl_config::short(&v)),
+        }
+    }
+
+    return ok;
+}
+
+/// Set a config value on all servers.  Everything is validated against the
+/// current values first so that nothing is changed if any server would fail
+fn run_config_set(servers: &[Pihole], key: &str, raw: &str) -> bool {
+    let mut changes: Vec<(&Pihole, Value, Value, bool)> = vec![];
+    for s in servers {
+        let detailed = match get_setting(s, key, true) {
+            Some(d) => d,
+            None => return false,
+        };
+        if !ftl_config::is_setting(&detailed) {
+            error!("{} is a section, not a single setting", key);
+            return false;
+        }
+
+        let old = detailed["value"].clone();
+        let new = match ftl_config::parse_value(raw, &old) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Invalid value for {} on {}: {}", key, s.base_url, e);
+                return false;
+            },
+        };
+        changes.push((s, old, new, ftl_config::needs_restart(&detailed)));
+    }
+
+    let mut ok = true;
+    for (s, old, new, restart) in changes {
+        if old == new {
+            println!("{}: {} unchanged ({})", s.base_url, key,
+                ftl_config::short(&old));
+            continue;
+        }
+
+        match s.set_config(key, new.clone()) {
+            Some(v) if v.get("error").is_none() => {
+                println!("{}: {} {} -> {}{}", s.base_url, key,
+                    ftl_config::short(&old), ftl_config::short(&new),
+                    if restart { " (FTL restarts)" } else { "" });
+            },
+            Some(v) => {
+                error!("Failed to set {} on {}: {}", key, s.base_url,
+                    v["error"]["message"]);
+                ok = false;
+            },
+            None => {
+                error!("Failed to set {} on {}", key, s.base_url);
+                ok = false;
+            },
+        }
+    }
+
+    return ok;
+}
+
+/// Print a setting for every server, marking the ones that differ from the
+/// first server.  Returns false if they aren't all the same
+fn run_config_diff(servers: &[Pihole], key: &str) -> bool {
+    let values: Vec<(&Pihole, Option<Value>)> = servers
+        .iter()
+        .map(|s| (s, get_setting(s, key, false)))
+        .collect();
+    if values.is_empty() {
+        return true;
+    }
+    // Compare against the first server that answered, so one that's down
+    // doesn't make all the others look different
+    let reference = values.iter().find_map(|(_, v)| v.clone());
+    if reference.is_none() {
+        error!("Couldn't get {} from any of the servers", key);
+        return false;
+    }
+
+    let mut same = true;
+    for (s, v) in &values {
+        let val = match v {
+            Some(v) => ftl_config::short(v),
+            None => "<unavailable>".to_string(),
+        };
+        if *v == reference {
+            println!("  {}: {}", s.base_url, val);
+        } else {
+            same = false;
+            println!("* {}: {}", s.base_url, val);
+        }
+    }
+
+    if same {
+        println!("{} is the same on all {} servers", key, values.len());
+    } else {
+        println!("{} differs between servers (marked with *)", key);
+    }
+
+    return same;
+}
+
+/// Change the `dns.upstreams` setting on all servers.  The new upstreams
+/// are validated, and optionally checked, before anything is changed
+fn run_upstreams_change(
+    servers: &[Pihole],
+    action: &str,
+    matches: &ArgMatches,
+) -> bool {
+    let upstreams: Vec<&str> = matches.values_of("upstream").unwrap().collect();
+    for u in &upstreams {
+        if let Err(e) = upstream::parse_upstream(u) {
+            error!("Invalid upstream {}: {}", u, e);
+            return false;
+        }
+    }
+
+    if action != "remove" && matches.is_present("check") {
+        let name = matches.value_of("test_name").unwrap();
+        let mut failed = false;
+        for u in &upstreams {
+            let addr = upstream::parse_upstream(u).unwrap();
+            match upstream::check_upstream(&addr, name, Duration::from_secs(3)) {
+                Ok(_) => println!("{} resolved {}", u, name),
+                Err(e) => {
+                    error!("{} failed to resolve {}: {}", u, name, e);
+                    failed = true;
+                },
+            }
+        }
+        if failed {
+            error!("Not changing any upstreams since the check failed");
+            return false;
+        }
+    }
+
+    let key = "dns.upstreams";
+    let mut changes: Vec<(&Pihole, Vec<Value>, Vec<Value>)> = vec![];
+    for s in servers {
+        let current: Vec<Value> = match get_setting(s, key, false) {
+            Some(Value::Array(a)) => a,
+            _ => {
+                error!("Couldn't get the current upstreams for {}",
+                    s.base_url);
+                return false;
+            },
+        };
+
+        let mut new: Vec<Value> = match action {
+            "set" => vec![],
+            _ => current.clone(),
+        };
+        for u in &upstreams {
+            let val = Value::String(u.to_string());
+            if action == "remove" {
+                if !new.contains(&val) {
+                    warn!("{} is not an upstream for {}", u, s.base_url);
+                }
+                new.retain(|v| *v != val);
+            } else if !new.contains(&val) {
+                new.push(val);
+            }
+        }
+        if new.is_empty() && !current.is_empty()
+            && !matches.is_present("force") {
+            error!("This would leave {} with no upstreams, use --force if \
+                that's really what you want", s.base_url);
+            return false;
+        }
+        changes.push((s, current, new));
+    }
+
+    let mut ok = true;
+    for (s, old, new) in changes {
+        if old == new {
+            println!("{}: upstreams unchanged", s.base_url);
+            continue;
+        }
+
+        match s.set_config(key, Value::Array(new.clone())) {
+            Some(v) if v.get("error").is_none() => {
+                println!("{}: upstreams {} -> {}", s.base_url,
+                    Value::Array(old), Value::Array(new));
+            },
+            Some(v) => {
+                error!("Failed to set the upstreams on {}: {}", s.base_url,
+                    v["error"]["message"]);
+                ok = false;
+            },
+            None => {
+                error!("Failed to set the upstreams on {}", s.base_url);
+                ok = false;
+            },
+        }
+    }
+
+    return ok;
+}
+
+/// Print the merged leases from all servers.  Returns false if a server
+/// failed or there are conflicting assignments
+fn run_dhcp_leases(
+    servers: &[Pihole],
+    matches: &ArgMatches,
+    fmt: Format,
+) -> bool {
+    let mut ok = true;
+    let mut leases: Vec<(String, dhcp::Lease)> = vec![];
+    for s in servers {
+        let resp = match s.dhcp_leases() {
+            Some(r) => r,
+            None => {
+                warn!("Couldn't get the DHCP leases for {}", s.base_url);
+                ok = false;
+                continue;
+            },
+        };
+        match dhcp::parse_leases(&resp) {
+            Ok(l) => leases.extend(
+                l.into_iter().map(|x| (s.base_url.clone(), x))),
+            Err(e) => {
+                warn!("{}: {}", s.base_url, e);
+                ok = false;
+            },
+        }
+    }
+
+    let merged = dhcp::merge_leases(&leases);
+    if matches.is_present("hosts") {
+        print!("{}", dhcp::to_hosts(&merged));
+    } else {
+        let mut table = output::Table::new(&["!", "IP", "MAC", "NAME",
+            "EXPIRES", "SERVERS"]);
+        for m in &merged {
+            table.add_row(vec![
+                if m.conflict { "!" } else { "" }.to_string(),
+                m.lease.ip.clone(),
+                m.lease.hwaddr.clone(),
+                m.lease.name.clone(),
+                fmt_ts(m.lease.expires, "never"),
+                m.servers.join(", "),
+            ]);
+        }
+        let items: Vec<Value> = merged.iter()
+            .filter_map(|m| serde_json::to_value(m).ok())
+            .collect();
+        print!("{}", output::render_list(fmt, &items, &table));
+    }
+
+    for c in dhcp::find_conflicts(&leases) {
+        warn!("Duplicate DHCP assignment: {}", c.message);
+        ok = false;
+    }
+
+    return ok;
+}
+
+fn run_dhcp_delete(servers: &[Pihole], matches: &ArgMatches) -> bool {
+    let ip = match dhcp::parse_lease_ip(matches.value_of("ip").unwrap()) {
+        Ok(ip) => ip,
+        Err(e) => {
+            error!("{}", e);
+            return false;
+        },
+    };
+    let target = matches.value_of("server").unwrap();
+    let targets = select_servers(servers, target);
+    if targets.is_empty() {
+        error!("No configured server matches '{}'", target);
+        return false;
+    }
+
+    let mut ok = true;
+    for s in targets {
+        if s.delete_dhcp_lease(&ip) {
+            println!("Deleted the lease for {} on {}", ip, s.base_url);
+        } else {
+            error!("Failed to delete the lease for {} on {}", ip, s.base_url);
+            ok = false;
+        }
+    }
+
+    return ok;
+}
+
+/// Print the merged device table, optionally only the stale devices, and
+/// delete those from every server if asked to
+fn run_network_devices(
+    servers: &[Pihole],
+    matches: &ArgMatches,
+    fmt: Format,
+) -> bool {
+    let mut ok = true;
+    let mut devices: Vec<(String, network::Device)> = vec![];
+    for s in servers {
+        let parsed = match s.network_devices() {
+            Some(r) => network::parse_devices(&r),
+            None => Err("no response".to_string()),
+        };
+        match parsed {
+            Ok(d) => devices.extend(
+                d.into_iter().map(|x| (s.base_url.clone(), x))),
+            Err(e) => {
+                warn!("Couldn't get the network devices for {}: {}",
+                    s.base_url, e);
+                ok = false;
+            },
+        }
+    }
+
+    let merged = network::merge_devices(&devices);
+    let shown: Vec<&network::MergedDevice> = match matches.value_of("stale_days") {
+        None => merged.iter().collect(),
+        Some(_) => {
+            let days = value_t!(matches, "stale_days", i64)
+                .unwrap_or_else(|e| e.exit());
+            let cutoff = chrono::Utc::now().timestamp() - days * 86400;
+            network::stale_devices(&merged, cutoff)
+        },
+    };
+
+    let mut table = output::Table::new(&["MAC", "NAMES", "IPS", "FIRST SEEN",
+        "LAST SEEN", "QUERIES"]);
+    for d in &shown {
+        let names: Vec<&str> = d.names.iter().map(|n| n.as_str()).collect();
+        let ips: Vec<&str> = d.ips.iter().map(|n| n.as_str()).collect();
+        let queries: Vec<String> = d.queries.iter()
+            .map(|(srv, n)| format!("{}={}", srv, n))
+            .collect();
+        table.add_row(vec![
+            d.hwaddr.clone(),
+            names.join(","),
+            ips.join(","),
+            fmt_ts(d.first_seen, "never"),
+            fmt_ts(d.last_seen, "never"),
+            queries.join(" "),
+        ]);
+    }
+    let items: Vec<Value> = shown.iter()
+        .filter_map(|d| serde_json::to_value(d).ok())
+        .collect();
+    print!("{}", output::render_list(fmt, &items, &table));
+
+    if matches.is_present("delete") {
+        // A server that didn't answer might have a newer sighting of any of
+        // these devices, so they wouldn't really be stale
+        if !ok {
+            error!("Not deleting any devices since not every server \
+                returned its device list");
+            return false;
+        }
+        for d in &shown {
+            for (srv, id) in &d.ids {
+                let s = match servers.iter().find(|s| &s.base_url == srv) {
+                    Some(s) => s,
+                    None => continue,
+                };
+                if s.delete_network_device(*id) {
+                    println!("Deleted {} from {}", d.hwaddr, srv);
+                } else {
+                    error!("Failed to delete {} from {}", d.hwaddr, srv);
+                    ok = false;
+                }
+            }
+        }
+    }
+
+    return ok;
+}
+
+/// Print the health table and return the worst level of all the servers
+fn run_health(
+    servers: &[Pihole],
+    matches: &ArgMatches,
+    fmt: Format,
+) -> health::Level {
+    let limits = health::Thresholds {
+        load: value_t!(matches, "max_load", f64).unwrap_or_else(|e| e.exit()),
+        mem_pct: value_t!(matches, "max_mem", f64)
+            .unwrap_or_else(|e| e.exit()),
+        temp: value_t!(matches, "max_temp", f64).unwrap_or_else(|e| e.exit()),
+        gravity_days: value_t!(matches, "max_gravity_age", f64)
+            .unwrap_or_else(|e| e.exit()),
+    };
+    let use_color = !matches.is_present("no_color") && color::stdout_is_tty();
+    let now = chrono::Utc::now().timestamp();
+    let na = || "-".to_string();
+
+    if fmt == Format::Table {
+        println!("{:<30} {:<8} {:<6} {:<6} {:<6} {:<8} {:<4} {:<7} {:<8} \
+            {:<8}", "SERVER", "UPTIME", "LOAD", "MEM", "TEMP", "FTL PID",
+            "PRIV", "DB SIZE", "GRAVITY", "CLIENTS");
+    }
+
+    let mut worst = health::Level::Ok;
+    let mut results: output::Results = vec![];
+    for s in servers {
+        let h = health::Health::from_values(
+            &s.base_url,
+            s.info_system().as_ref(),
+            s.info_ftl().as_ref(),
+            s.info_database().as_ref(),
+            s.info_sensors().as_ref(),
+            s.summary().as_ref(),
+        );
+        let (level, reasons) = h.evaluate(&limits, now);
+        worst = worst.max(level);
+
+        if fmt != Format::Table {
+            let mut v = serde_json::to_value(&h).unwrap();
+            v["level"] = serde_json::to_value(level).unwrap();
+            v["reasons"] = serde_json::to_value(&reasons).unwrap();
+            results.push((s.base_url.clone(), Some(v)));
+            continue;
+        }
+
+        let row = format!(
+            "{:<30} {:<8} {:<6} {:<6} {:<6} {:<8} {:<4} {:<7} {:<8} {:<8}",
+            h.server,
+            h.uptime.map(health::fmt_duration).unwrap_or_else(na),
+            h.load.map(|l| format!("{:.2}", l)).unwrap_or_else(na),
+            h.mem_pct.map(|m| format!("{:.0}%", m)).unwrap_or_else(na),
+            h.cpu_temp.map(|t| format!("{:.0}{}", t,
+                h.temp_unit.as_deref().unwrap_or(""))).unwrap_or_else(na),
+            h.ftl_pid.map(|p| p.to_string()).unwrap_or_else(na),
+            h.privacy_level.map(|p| p.to_string()).unwrap_or_else(na),
+            h.db_size.map(health::fmt_bytes).unwrap_or_else(na),
+            h.gravity_age_days(now)
+                .map(|d| health::fmt_duration((d * 86400.0) as i64))
+                .unwrap_or_else(na),
+            match (h.clients_active, h.clients_total) {
+                (Some(a), Some(t)) => format!("{}/{}", a, t),
+                _ => na(),
+            },
+        );
+        let c = match level {
+            health::Level::Ok => Color::Green,
+            health::Level::Warning => Color::Yellow,
+            health::Level::Critical => Color::Red,
+        };
+        println!("{}", color::paint(&row, c, use_color));
+        if !reasons.is_empty() {
+            println!("    {}", reasons.join(", "));
+        }
+    }
+
+    if fmt != Format::Table {
+        print!("{}", output::render(fmt, &results, None).unwrap());
+    }
+
+    return worst;
+}
+
+/// Print the diagnosis messages from all servers, oldest first
+fn run_messages(servers: &[Pihole], matches: &ArgMatches, fmt: Format) -> bool {
+    let since = match matches.value_of("since") {
+        None => None,
+        Some(v) => {
+            match messages::parse_since(v, chrono::Utc::now().timestamp()) {
+                Ok(ts) => Some(ts),
+                Err(e) => {
+                    error!("Invalid --since: {}", e);
+                    return false;
+                },
+            }
+        },
+    };
+
+    let mut ok = true;
+    let mut msgs: Vec<(&str, messages::Message)> = vec![];
+    for s in servers {
+        let parsed = match s.messages() {
+            Some(r) => messages::parse_messages(&r),
+            None => Err("no response".to_string()),
+        };
+        match parsed {
+            Ok(m) => msgs.extend(m.into_iter().map(|x| (&s.base_url[..], x))),
+            Err(e) => {
+                warn!("Couldn't get the messages for {}: {}", s.base_url, e);
+                ok = false;
+            },
+        }
+    }
+
+    if let Some(ts) = since {
+        msgs.retain(|(_, m)| m.timestamp >= ts as f64);
+    }
+    msgs.sort_by(|a, b| a.1.timestamp.total_cmp(&b.1.timestamp));
+
+    if fmt != Format::Table {
+        let mut table = output::Table::new(&["TIME", "SERVER", "TYPE", "ID",
+            "MESSAGE"]);
+        let mut items: Vec<Value> = vec![];
+        for (srv, m) in &msgs {
+            table.add_row(vec![fmt_ts(m.timestamp as i64, "-"),
+                srv.to_string(), m.mtype.clone(), m.id.to_string(),
+                m.plain.clone()]);
+            let mut v = serde_json::to_value(m).unwrap();
+            v["server"] = Value::String(srv.to_string());
+            items.push(v);
+        }
+        print!("{}", output::render_list(fmt, &items, &table));
+        return ok;
+    }
+
+    for (srv, m) in &msgs {
+        println!("{} {} [{}] #{}: {}", fmt_ts(m.timestamp as i64, "-"), srv,
+            m.mtype, m.id, m.plain);
+    }
+
+    return ok;
+}
+
+fn run_messages_delete(servers: &[Pihole], matches: &ArgMatches) -> bool {
+    let target = matches.value_of("server").unwrap();
+    let targets = select_servers(servers, target);
+    if targets.len() != 1 {
+        error!("Message IDs are per server, '{}' must match exactly one \
+            server", target);
+        return false;
+    }
+    let s = targets[0];
+
+    let ids = match values_t!(matches, "id", i64) {
+        Ok(ids) => ids,
+        Err(e) => e.exit(),
+    };
+
+    let mut ok = true;
+    for id in ids {
+        if s.delete_message(id) {
+            println!("Deleted message #{} on {}", id, s.base_url);
+        } else {
+            error!("Failed to delete message #{} on {}", id, s.base_url);
+            ok = false;
+        }
+    }
+
+    return ok;
+}
+
+/// Push the summary and query type stats for all servers, once or forever
+/// on an interval.  Returns false if a one-off push wasn't complete
+fn run_push_metrics(servers: &mut [Pihole], matches: &ArgMatches) -> bool {
+    let url = matches.value_of("to").unwrap();
+    let to: push::Endpoint = match url.parse() {
+        Ok(e) => e,
+        Err(e) => {
+            error!("{}", e);
+            return false;
+        },
+    };
+    let proto: push::Protocol = matches.value_of("format").unwrap()
+        .parse().unwrap();
+    let prefix = matches.value_of("prefix").unwrap();
+    let mut headers: Vec<(String, String)> = vec![];
+    for h in matches.values_of("header").unwrap_or_default() {
+        match h.split_once(':') {
+            Some((k, v)) => headers.push((k.trim().to_string(),
+                v.trim().to_string())),
+            None => {
+                error!("Invalid header, expected 'Name: value': {}", h);
+                return false;
+            },
+        }
+    }
+    let interval = matches.value_of("interval").map(|_| {
+        value_t!(matches, "interval", u64).unwrap_or_else(|e| e.exit()).max(1)
+    });
+    let ok = |v: Option<Value>| v.filter(|x| x.get("error").is_none());
+
+    loop {
+        let now = chrono::Utc::now().timestamp();
+        let samples: Vec<push::Sample> = servers.iter_mut()
+            .map(|s| {
+                let summary = ok(s.summary());
+                if summary.is_none() {
+                    warn!("Couldn't get a summary for {}", s.base_url);
+                    // The session may have expired while looping.  End it
+                    // first, so a server that keeps failing doesn't run out
+                    // of sessions
+                    s.logout();
+                    s.auth();
+                }
+                push::Sample {
+                    server: s.base_url.clone(),
+                    timestamp: now,
+                    summary,
+                    query_types: ok(s.get_query_types()),
+                }
+            })
+            .collect();
+
+        let lines = push::format(proto, &samples, prefix);
+        let sent = match to.send(&lines, &headers) {
+            Ok(_) => {
+                debug!("Pushed {} lines to {}", lines.len(), url);
+                true
+            },
+            Err(e) => {
+                error!("Failed to push the metrics to {}: {}", url, e);
+                false
+            },
+        };
+
+        match interval {
+            Some(secs) => thread::sleep(Duration::from_secs(secs)),
+            None => return sent && samples.iter().all(|s| s.summary.is_some()),
+        }
+    }
+}
+
+/// Run the Nagios checks, print the status line and return the state
+fn run_check(servers: &[Pihole], matches: &ArgMatches) -> check::State {
+    let checks: Vec<&str> = matches.values_of("checks").unwrap().collect();
+    let limits = check::Limits {
+        gravity_days: value_t!(matches, "gravity_days", f64)
+            .unwrap_or_else(|e| e.exit()),
+        window_mins: value_t!(matches, "window", i64)
+            .unwrap_or_else(|e| e.exit()),
+        min_queries: value_t!(matches, "min_queries", i64)
+            .unwrap_or_else(|e| e.exit()),
+    };
+    let on = |c: &str| checks.contains(&c);
+    let ok = |v: Option<Value>| v.filter(|x| x.get("error").is_none());
+    let now = chrono::Utc::now().timestamp();
+
+    let outcomes: Vec<(String, check::Outcome)> = servers.iter()
+        .map(|s| {
+            let blocking = ok(s.blocking());
+            let mut d = check::ServerData {
+                name: backup::server_slug(&s.base_url),
+                reachable: blocking.is_some(),
+                blocking,
+                ..Default::default()
+            };
+            if d.reachable {
+                if on("gravity") {
+                    d.summary = ok(s.summary());
+                }
+                if on("version") {
+                    d.version = ok(s.version());
+                }
+                if on("queries") {
+                    d.queries = ok(s.queries(now - limits.window_mins * 60,
+                        now, 1));
+                }
+                if on("messages") {
+                    d.messages = ok(s.messages());
+                }
+            }
+            let outcome = check::evaluate(&d, &checks, &limits, now);
+            (d.name, outcome)
+        })
+        .collect();
+
+    let (state, line) = check::status_line(&outcomes);
+    println!("{}", line);
+
+    return state;
+}
+
+/// Print the most recent log lines for all servers, interleaved by time,
+/// and with `--follow` keep polling each server in its own thread
+fn run_logs(servers: &[Pihole], matches: &ArgMatches) -> bool {
+    let log = matches.value_of("log").unwrap();
+    let lines = value_t!(matches, "lines", usize).unwrap_or_else(|e| e.exit());
+    let interval = value_t!(matches, "interval", u64)
+        .unwrap_or_else(|e| e.exit());
+    let use_color = !matches.is_present("no_color") && color::stdout_is_tty();
+    let width = servers.iter().map(|s| s.name().len()).max().unwrap_or(0);
+    let prefix = |i: usize, s: &Pihole| {
+        color::paint(&format!("{:<w$}", s.name(), w = width),
+            Color::nth(i), use_color)
+    };
+
+    let mut ok = true;
+    let mut cursors: Vec<Option<i64>> = vec![];
+    let mut started: Vec<bool> = vec![];
+    let mut all: Vec<(usize, logs::LogLine)> = vec![];
+    for (i, s) in servers.iter().enumerate() {
+        let parsed = match s.logs(log, None) {
+            Some(r) => logs::parse_log(&r),
+            None => Err("no response".to_string()),
+        };
+        match parsed {
+            Ok((l, next)) => {
+                all.extend(logs::tail(l, lines).into_iter().map(|x| (i, x)));
+                cursors.push(next);
+                started.push(true);
+            },
+            Err(e) => {
+                warn!("Couldn't get the {} log for {}: {}", log, s.base_url, e);
+                cursors.push(None);
+                started.push(false);
+                ok = false;
+            },
+        }
+    }
+
+    all.sort_by(|a, b| a.1.timestamp.total_cmp(&b.1.timestamp));
+    for (i, l) in &all {
+        println!("{} {} {}", prefix(*i, &servers[*i]),
+            fmt_ts(l.timestamp as i64, "-"), l.message);
+    }
+
+    if !matches.is_present("follow") {
+        return ok;
+    }
+
+    let (tx, rx) = mpsc::channel::<(usize, logs::LogLine)>();
+    thread::scope(|scope| {
+        for (i, s) in servers.iter().enumerate() {
+            let tx = tx.clone();
+            let mut cursor = cursors[i];
+            let mut started = started[i];
+            scope.spawn(move || loop {
+                thread::sleep(Duration::from_secs(interval));
+                let resp = match s.logs(log, cursor) {
+                    Some(r) => r,
+                    None => continue,
+                };
+                match logs::parse_log(&resp) {
+                    Ok((l, next)) => {
+                        cursor = next.or(cursor);
+                        // A server that was down at the start sends its
+                        // whole buffer the first time, which isn't new
+                        if !started {
+                            started = true;
+                            continue;
+                        }
+                        for line in l {
+                            if tx.send((i, line)).is_err() {
+                                return;
+                            }
+                        }
+                    },
+                    Err(e) => warn!("Bad {} log from {}: {}", log,
+                        s.base_url, e),
+                }
+            });
+        }
+        drop(tx);
+
+        for (i, l) in rx {
+            println!("{} {} {}", prefix(i, &servers[i]),
+                fmt_ts(l.timestamp as i64, "-"), l.message);
+        }
+    });
+
+    return ok;
+}
+
+/// Print the installed vs available versions of each component for each
+/// server.  Returns false if any server is behind or couldn't be checked
+fn run_version_outdated(servers: &[Pihole], fmt: Format) -> bool {
+    let mut ok = true;
+    let mut all: Vec<Versions> = vec![];
+    let mut results: output::Results = vec![];
+    let mut table = output::Table::new(&["SERVER", "COMPONENT", "INSTALLED",
+        "AVAILABLE", "STATUS"]);
+
+    for s in servers {
+        let parsed = match s.version() {
+            Some(v) => Versions::from_value(&v),
+            None => Err("no response".to_string()),
+        };
+        let vers = match parsed {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Couldn't get a version for {}: {}", s.base_url, e);
+                results.push((s.base_url.clone(), None));
+                ok = false;
+                continue;
+            },
+        };
+
+        for name in version::COMPONENTS {
+            let c = vers.get(name).unwrap();
+            // Not every server has every component, ex: docker
+            let local = match &c.local {
+                Some(l) => l,
+                None => continue,
+            };
+            let status = if c.is_outdated() {
+                ok = false;
+                "outdated"
+            } else if c.remote.is_none() {
+                "unknown"
+            } else {
+                "current"
+            };
+            table.add_row(vec![s.base_url.clone(), name.to_string(),
+                local.clone(), c.remote.clone().unwrap_or_else(|| "-".into()),
+                status.to_string()]);
+        }
+        results.push((s.base_url.clone(), serde_json::to_value(&vers).ok()));
+        all.push(vers);
+    }
+
+    print!("{}", output::render(fmt, &results, Some(&table)).unwrap());
+
+    let refs: Vec<&Versions> = all.iter().collect();
+    for c in version::mismatched(&refs) {
+        warn!("The servers are running different versions of {}", c);
+    }
+
+    return ok;
+}
+
 fn main() {
     let def_conf = format!("{}/.mpihole", env::var("HOME").ok().unwrap());
     let args = get_args(&def_conf);
@@ -305,22 +1752,34 @@ fn main() {
         }
     };
 
-    if args.is_present("reconfigure") || conf.servers.len() < 1{
+    if args.is_present("reconfigure") || conf.servers.is_empty() {
         configure(conf_path, Some(conf));
         std::process::exit(0);
     }
 
-    let servers: Vec<Pihole> = conf.servers
+    // These report on servers that are down rather than giving up, and
+    // push-metrics logs in again on its own once a server comes back
+    let report_unreachable = args.subcommand_matches("health").is_some()
+        || args.subcommand_matches("top").is_some()
+        || args.subcommand_matches("check").is_some()
+        || args.subcommand_matches("push-metrics").is_some();
+    let mut servers: Vec<Pihole> = conf.servers
         .iter()
         .map(|x| {
             let mut ph = Pihole::from_cfg(x);
-            ph.auth().expect(
-                &format!("Failed to authenticate with server: {}", x.base_url)
-            );
+            if ph.auth().is_none() {
+                if !report_unreachable {
+                    panic!("Failed to authenticate with server: {}",
+                        x.base_url);
+                }
+                error!("Failed to authenticate with server: {}", x.base_url);
+            }
             ph
         })
         .collect();
 
+    let fmt: Format = args.value_of("output").unwrap().parse().unwrap();
+
     // Handle the subcommands
     if let Some(matches) = args.subcommand_matches("disable") {
         let secs = value_t!(matches, "time", usize).ok().unwrap();
@@ -328,101 +1787,187 @@ fn main() {
             debug!("Disabling '{}' for {} secs", s.base_url, secs);
             s.disable(secs);
         }
-    } else if let Some(_) = args.subcommand_matches("enable") {
+    } else if args.subcommand_matches("enable").is_some() {
         for s in &servers {
             debug!("Enabling '{}'", s.base_url);
             s.enable();
         }
-    } else if let Some(_) = args.subcommand_matches("summary") {
-        for s in &servers {
-            println!("Summary for {}", s.base_url);
-            match s.summary() {
-                None => warn!("Couldn't get a summary for {}", s.base_url),
-                Some(v) => println!("{}",
-                    serde_json::to_string_pretty(&v).ok().unwrap()
-                ),
-            }
-            println!();
+    } else if let Some(matches) = args.subcommand_matches("summary") {
+        let res = collect(&servers, "a summary", |s| s.summary());
+        if matches.is_present("aggregate") {
+            let agg = aggregate::summary(&res);
+            let table = tables::fleet_summary(&res, &agg);
+            print_fleet(fmt, &res, agg, &table);
+        } else {
+            print_results(fmt, &res, Some(&tables::summary(&res)),
+                "Summary for");
         }
-    } else if let Some(_) = args.subcommand_matches("version") {
-        for s in &servers {
-            match s.version() {
-                None => warn!("Couldn't get a version for {}", s.base_url),
-                Some(v) => {
-                    println!("Version info for {}", s.base_url);
-                    println!("{}",
-                        serde_json::to_string_pretty(&v).ok().unwrap()
-                    );
-                }
+    } else if let Some(matches) = args.subcommand_matches("version") {
+        if matches.is_present("outdated") {
+            if !run_version_outdated(&servers, fmt) {
+                std::process::exit(1);
             }
-            println!();
+            return;
         }
+        let res = collect(&servers, "a version", |s| s.version());
+        print_results(fmt, &res, None, "Version info for");
     } else if let Some(matches) = args.subcommand_matches("top-domains") {
         let topn = value_t!(matches, "topn", usize).ok().unwrap();
-        for s in &servers {
-            println!("The top {} domains for {}", topn, s.base_url);
-            match s.top_items(Some(topn)) {
-                None => warn!("Couldn't get top domains for {}", s.base_url),
-                Some(v) => println!("{}",
-                    serde_json::to_string_pretty(&v).ok().unwrap()
-                ),
-            }
+        if matches.is_present("aggregate") {
+            let res = collect(&servers, "top domains",
+                |s| s.top_items(Some(fleet_fetch_count(topn))));
+            let agg = aggregate::top_items(&res, "domains", "domain", topn);
+            let table = tables::fleet_top(&agg, "domains", "domain",
+                &server_urls(&servers), false);
+            print_fleet(fmt, &res, agg, &table);
+        } else {
+            let res = collect(&servers, "top domains",
+                |s| s.top_items(Some(topn)));
+            print_results(fmt, &res, Some(&tables::top_domains(&res)),
+                &format!("The top {} domains for", topn));
         }
     } else if let Some(matches) = args.subcommand_matches("top-clients") {
         let topn = value_t!(matches, "topn", usize).ok().unwrap();
-        for s in &servers {
-            println!("The top {} clients for {}", topn, s.base_url);
-            match s.top_clients(Some(topn)) {
-                None => warn!("Couldn't get top clients for {}", s.base_url),
-                Some(v) => println!("{}",
-                    serde_json::to_string_pretty(&v).ok().unwrap()
-                ),
-            }
+        if matches.is_present("aggregate") {
+            let res = collect(&servers, "top clients",
+                |s| s.top_clients(Some(fleet_fetch_count(topn))));
+            let agg = aggregate::top_items(&res, "clients", "ip", topn);
+            let table = tables::fleet_top(&agg, "clients", "ip",
+                &server_urls(&servers), true);
+            print_fleet(fmt, &res, agg, &table);
+        } else {
+            let res = collect(&servers, "top clients",
+                |s| s.top_clients(Some(topn)));
+            print_results(fmt, &res, Some(&tables::top_clients(&res)),
+                &format!("The top {} clients for", topn));
         }
-    } else if let Some(_) = args.subcommand_matches("upstreams") {
-        for s in &servers {
-            println!("Forward destinations for {}", s.base_url);
-            match s.get_upstreams() {
-                None => warn!("Couldn't get forward destinations for {}",
-                    s.base_url),
-                Some(v) => println!("{}",
-                    serde_json::to_string_pretty(&v).ok().unwrap()
-                ),
+    } else if let Some(matches) = args.subcommand_matches("upstreams") {
+        if let (action, Some(m)) = matches.subcommand() {
+            if !run_upstreams_change(&servers, action, m) {
+                std::process::exit(1);
             }
-            println!();
+            return;
         }
-    } else if let Some(_) = args.subcommand_matches("query-types") {
-        for s in &servers {
-            println!("Query types for {}", s.base_url);
-            match s.get_query_types() {
-                None => warn!("Couldn't get query types for {}", s.base_url),
-                Some(v) => println!("{}",
-                    serde_json::to_string_pretty(&v).ok().unwrap()
-                ),
-            }
-            println!();
+        let res = collect(&servers, "forward destinations",
+            |s| s.get_upstreams());
+        if matches.is_present("aggregate") {
+            let agg = aggregate::upstreams(&res);
+            let table = tables::fleet_upstreams(&agg, &server_urls(&servers));
+            print_fleet(fmt, &res, agg, &table);
+        } else {
+            print_results(fmt, &res, Some(&tables::upstreams(&res)),
+                "Forward destinations for");
+        }
+    } else if let Some(matches) = args.subcommand_matches("query-types") {
+        let res = collect(&servers, "query types", |s| s.get_query_types());
+        if matches.is_present("aggregate") {
+            let agg = aggregate::query_types(&res);
+            let table = tables::fleet_query_types(&res, &agg);
+            print_fleet(fmt, &res, agg, &table);
+        } else {
+            print_results(fmt, &res, Some(&tables::query_types(&res)),
+                "Query types for");
         }
     } else if let Some(matches) = args.subcommand_matches("recent-blocked") {
-        for s in &servers {
-            let num = value_t!(matches, "num", usize).ok().unwrap();
-            match s.recent_blocked(num) {
-                None => warn!("Couldn't get most recent blocked for {}",
-                    s.base_url),
-                Some(v) => {
-                    println!("Most recent blocked for {}", s.base_url);
-                    for dom in v["blocked"].as_array().unwrap() {
-                        println!("{}", dom.as_str().unwrap());
-                    }
-                }
-            }
-            println!();
+        let num = value_t!(matches, "num", usize).ok().unwrap();
+        let res = collect(&servers, "most recent blocked",
+            |s| s.recent_blocked(num));
+        print_results(fmt, &res, Some(&tables::recent_blocked(&res)),
+            "Most recent blocked for");
+    } else if let Some(matches) = args.subcommand_matches("backup") {
+        if !run_backup(&servers, matches) {
+            std::process::exit(1);
         }
-    } else if let Some(_) = args.subcommand_matches("status") {
-        for s in &servers {
-            match s.status() {
-                None => warn!("Couldn't get status for {}", s.base_url),
-                Some(v) => println!("{}: {}", s.base_url, v),
-            }
+    } else if let Some(matches) = args.subcommand_matches("restore") {
+        if !run_restore(&servers, matches) {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("config") {
+        let ok = match matches.subcommand() {
+            ("get", Some(m)) => run_config_get(
+                &servers, m.value_of("key").unwrap(), fmt),
+            ("set", Some(m)) => run_config_set(
+                &servers,
+                m.value_of("key").unwrap(),
+                m.value_of("value").unwrap(),
+            ),
+            ("diff", Some(m)) => run_config_diff(
+                &servers, m.value_of("key").unwrap()),
+            _ => true,
+        };
+        if !ok {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("dhcp") {
+        let ok = match matches.subcommand() {
+            ("leases", Some(m)) => run_dhcp_leases(&servers, m, fmt),
+            ("delete", Some(m)) => run_dhcp_delete(&servers, m),
+            _ => true,
+        };
+        if !ok {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("network") {
+        let ok = match matches.subcommand() {
+            ("devices", Some(m)) => run_network_devices(&servers, m, fmt),
+            ("gateway", _) => {
+                let res = collect(&servers, "the gateway",
+                    |s| s.network_gateway());
+                print_results(fmt, &res, None, "Gateway for");
+                true
+            },
+            ("interfaces", _) => {
+                let res = collect(&servers, "the interfaces",
+                    |s| s.network_interfaces());
+                print_results(fmt, &res, None, "Interfaces for");
+                true
+            },
+            _ => true,
+        };
+        if !ok {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("health") {
+        match run_health(&servers, matches, fmt) {
+            health::Level::Ok => {},
+            health::Level::Warning => std::process::exit(1),
+            health::Level::Critical => std::process::exit(2),
+        }
+    } else if let Some(matches) = args.subcommand_matches("messages") {
+        let ok = match matches.subcommand() {
+            ("delete", Some(m)) => run_messages_delete(&servers, m),
+            _ => run_messages(&servers, matches, fmt),
+        };
+        if !ok {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("check") {
+        std::process::exit(run_check(&servers, matches).code());
+    } else if let Some(matches) = args.subcommand_matches("push-metrics") {
+        if !run_push_metrics(&mut servers, matches) {
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("top") {
+        let opts = top::Options {
+            interval: Duration::from_secs(value_t!(matches, "interval", u64)
+                .unwrap_or_else(|e| e.exit()).max(1)),
+            disable_secs: value_t!(matches, "time", usize)
+                .unwrap_or_else(|e| e.exit()),
+            recent: value_t!(matches, "recent", usize)
+                .unwrap_or_else(|e| e.exit()),
+            clients: value_t!(matches, "clients", usize)
+                .unwrap_or_else(|e| e.exit()),
+        };
+        if let Err(e) = top::run(&servers, &opts) {
+            error!("The dashboard failed: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Some(matches) = args.subcommand_matches("logs") {
+        if !run_logs(&servers, matches) {
+            std::process::exit(1);
         }
+    } else if args.subcommand_matches("status").is_some() {
+        let res = collect(&servers, "status", |s| s.blocking());
+        print_results(fmt, &res, Some(&tables::status(&res)), "Status for");
     }
 }
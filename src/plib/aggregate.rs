@@ -0,0 +1,250 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use serde_json::{Map, Value, json};
+use super::output::Results;
+
+/// Merge the `stats/summary` results from all servers.  Counts are summed
+/// and the blocked percentage is recomputed from the sums.  The unique
+/// domains, active clients and gravity size can't be summed, since the
+/// servers share most of them, so the largest is used
+pub fn summary(results: &Results) -> Value {
+    let mut sums: BTreeMap<&str, i64> = BTreeMap::new();
+    let mut unique = 0;
+    let mut gravity = 0;
+    let mut active = 0;
+
+    for v in results.iter().filter_map(|(_, v)| v.as_ref()) {
+        for k in ["total", "blocked", "forwarded", "cached"] {
+            *sums.entry(k).or_insert(0) += v["queries"][k].as_i64().unwrap_or(0);
+        }
+        unique = unique.max(v["queries"]["unique_domains"].as_i64().unwrap_or(0));
+        gravity = gravity.max(
+            v["gravity"]["domains_being_blocked"].as_i64().unwrap_or(0));
+        active = active.max(v["clients"]["active"].as_i64().unwrap_or(0));
+    }
+
+    let total = sums.get("total").cloned().unwrap_or(0);
+    let blocked = sums.get("blocked").cloned().unwrap_or(0);
+    return json!({
+        "queries": {
+            "total": total,
+            "blocked": blocked,
+            "percent_blocked": percent(blocked, total),
+            "unique_domains": unique,
+            "forwarded": sums.get("forwarded").cloned().unwrap_or(0),
+            "cached": sums.get("cached").cloned().unwrap_or(0),
+        },
+        "clients": {"active": active},
+        "gravity": {"domains_being_blocked": gravity},
+    });
+}
+
+/// Merge the ranked lists (`domains` from `stats/top_domains` or `clients`
+/// from `stats/top_clients`) by `key`, summing the counts and re-ranking
+/// the top `top_n`.  Each item keeps the count from each server
+pub fn top_items(results: &Results, list: &str, key: &str, top_n: usize) -> Value {
+    let mut merged: BTreeMap<String, (Value, i64, Map<String, Value>)> =
+        BTreeMap::new();
+    let mut total_queries = 0;
+    let mut blocked_queries = 0;
+
+    for (server, v) in results {
+        let v = match v {
+            Some(v) => v,
+            None => continue,
+        };
+        total_queries += v["total_queries"].as_i64().unwrap_or(0);
+        blocked_queries += v["blocked_queries"].as_i64().unwrap_or(0);
+
+        for item in v[list].as_array().cloned().unwrap_or_default() {
+            let k = match item[key].as_str() {
+                Some(k) => k.to_string(),
+                None => continue,
+            };
+            let count = item["count"].as_i64().unwrap_or(0);
+            let entry = merged.entry(k)
+                .or_insert_with(|| (item.clone(), 0, Map::new()));
+            entry.1 += count;
+            entry.2.insert(server.clone(), json!(count));
+            // Prefer a name from any server that has one
+            if entry.0["name"].as_str().map(|n| n.is_empty()).unwrap_or(false) {
+                entry.0["name"] = item["name"].clone();
+            }
+        }
+    }
+
+    let mut items: Vec<(Value, i64, Map<String, Value>)> =
+        merged.into_values().collect();
+    items.sort_by_key(|i| std::cmp::Reverse(i.1));
+    items.truncate(top_n);
+
+    let ranked: Vec<Value> = items.into_iter()
+        .map(|(mut item, count, servers)| {
+            item["count"] = json!(count);
+            item["servers"] = Value::Object(servers);
+            item
+        })
+        .collect();
+
+    return json!({
+        list: ranked,
+        "total_queries": total_queries,
+        "blocked_queries": blocked_queries,
+    });
+}
+
+/// Merge the `stats/upstreams` results by upstream address.  The counts are
+/// summed and the response time is averaged, weighted by the counts
+pub fn upstreams(results: &Results) -> Value {
+    let mut merged: BTreeMap<String, (Value, i64, f64, Map<String, Value>)> =
+        BTreeMap::new();
+    let mut forwarded = 0;
+    let mut total = 0;
+
+    for (server, v) in results {
+        let v = match v {
+            Some(v) => v,
+            None => continue,
+        };
+        forwarded += v["forwarded_queries"].as_i64().unwrap_or(0);
+        total += v["total_queries"].as_i64().unwrap_or(0);
+
+        for u in v["upstreams"].as_array().cloned().unwrap_or_default() {
+            let key = format!("{}#{}", u["ip"].as_str().unwrap_or(""),
+                u["port"].as_i64().unwrap_or(0));
+            let count = u["count"].as_i64().unwrap_or(0);
+            let resp = u["statistics"]["response"].as_f64().unwrap_or(0.0);
+            let entry = merged.entry(key)
+                .or_insert_with(|| (u.clone(), 0, 0.0, Map::new()));
+            entry.1 += count;
+            entry.2 += resp * count as f64;
+            entry.3.insert(server.clone(), json!(count));
+        }
+    }
+
+    let mut items: Vec<(Value, i64, f64, Map<String, Value>)> =
+        merged.into_values().collect();
+    items.sort_by_key(|i| std::cmp::Reverse(i.1));
+
+    let ups: Vec<Value> = items.into_iter()
+        .map(|(mut u, count, weighted, servers)| {
+            u["count"] = json!(count);
+            u["statistics"] = json!({
+                "response": if count > 0 { weighted / count as f64 } else { 0.0 },
+            });
+            u["servers"] = Value::Object(servers);
+            u
+        })
+        .collect();
+
+    return json!({
+        "upstreams": ups,
+        "forwarded_queries": forwarded,
+        "total_queries": total,
+    });
+}
+
+/// Merge the `stats/query_types` results, summing each type and adding the
+/// percentage of the fleet total for each
+pub fn query_types(results: &Results) -> Value {
+    let mut sums: BTreeMap<String, i64> = BTreeMap::new();
+    for v in results.iter().filter_map(|(_, v)| v.as_ref()) {
+        if let Some(types) = v["types"].as_object() {
+            for (t, n) in types {
+                *sums.entry(t.clone()).or_insert(0) += n.as_i64().unwrap_or(0);
+            }
+        }
+    }
+
+    let total: i64 = sums.values().sum();
+    let mut types = Map::new();
+    let mut percents = Map::new();
+    for (t, n) in sums {
+        percents.insert(t.clone(), json!(percent(n, total)));
+        types.insert(t, json!(n));
+    }
+
+    return json!({"types": types, "percent": percents, "total": total});
+}
+
+fn percent(part: i64, total: i64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    return part as f64 * 100.0 / total as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let results: Results = vec![
+            ("a".to_string(), Some(json!({"queries": {"total": 100,
+                "blocked": 10, "unique_domains": 50},
+                "clients": {"active": 12}}))),
+            ("b".to_string(), Some(json!({"queries": {"total": 300,
+                "blocked": 90, "unique_domains": 70},
+                "clients": {"active": 8}}))),
+            ("c".to_string(), None),
+        ];
+        let agg = summary(&results);
+        assert_eq!(agg["queries"]["total"], json!(400));
+        assert_eq!(agg["queries"]["blocked"], json!(100));
+        assert_eq!(agg["queries"]["percent_blocked"], json!(25.0));
+        assert_eq!(agg["queries"]["unique_domains"], json!(70));
+        assert_eq!(agg["clients"]["active"], json!(12));
+    }
+
+    #[test]
+    fn test_top_items_reranks() {
+        let results: Results = vec![
+            ("a".to_string(), Some(json!({"domains": [
+                {"domain": "x.com", "count": 10},
+                {"domain": "y.com", "count": 8},
+            ], "total_queries": 100}))),
+            ("b".to_string(), Some(json!({"domains": [
+                {"domain": "y.com", "count": 7},
+                {"domain": "z.com", "count": 3},
+            ], "total_queries": 50}))),
+        ];
+        let agg = top_items(&results, "domains", "domain", 2);
+        let doms = agg["domains"].as_array().unwrap();
+        assert_eq!(doms.len(), 2);
+        assert_eq!(doms[0]["domain"], json!("y.com"));
+        assert_eq!(doms[0]["count"], json!(15));
+        assert_eq!(doms[0]["servers"]["a"], json!(8));
+        assert_eq!(doms[1]["domain"], json!("x.com"));
+        assert_eq!(agg["total_queries"], json!(150));
+    }
+
+    #[test]
+    fn test_upstreams_weighted_response() {
+        let results: Results = vec![
+            ("a".to_string(), Some(json!({"upstreams": [{"ip": "1.1.1.1",
+                "port": 53, "count": 1, "statistics": {"response": 0.01}}]}))),
+            ("b".to_string(), Some(json!({"upstreams": [{"ip": "1.1.1.1",
+                "port": 53, "count": 3, "statistics": {"response": 0.03}}]}))),
+        ];
+        let agg = upstreams(&results);
+        let u = &agg["upstreams"][0];
+        assert_eq!(u["count"], json!(4));
+        let resp = u["statistics"]["response"].as_f64().unwrap();
+        assert!((resp - 0.025).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_query_types() {
+        let results: Results = vec![
+            ("a".to_string(), Some(json!({"types": {"A": 3, "AAAA": 1}}))),
+            ("b".to_string(), Some(json!({"types": {"A": 1}}))),
+        ];
+        let agg = query_types(&results);
+        assert_eq!(agg["types"]["A"], json!(4));
+        assert_eq!(agg["percent"]["A"], json!(80.0));
+        assert_eq!(agg["total"], json!(5));
+    }
+}
@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use chrono::{DateTime, TimeZone};
+use serde_json::{Value, json};
+
+/// The categories that can be selected when restoring a teleporter archive.
+/// The ones beyond `config` and `dhcp_leases` are the gravity tables
+pub const IMPORT_CATEGORIES: &[&str] = &[
+    "config",
+    "dhcp_leases",
+    "group",
+    "adlist",
+    "adlist_by_group",
+    "domainlist",
+    "domainlist_by_group",
+    "client",
+    "client_by_group",
+];
+
+const GRAVITY_CATEGORIES: &[&str] = &[
+    "group",
+    "adlist",
+    "adlist_by_group",
+    "domainlist",
+    "domainlist_by_group",
+    "client",
+    "client_by_group",
+];
+
+/// Turn a server base url into something that is safe to use in a filename,
+/// ex: "https://pihole.example.com:8443" -> "pihole.example.com_8443"
+pub fn server_slug(base_url: &str) -> String {
+    let no_scheme = match base_url.find("://") {
+        Some(i) => &base_url[i + 3..],
+        None => base_url,
+    };
+
+    return no_scheme
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            c
+        } else {
+            '_'
+        })
+        .collect();
+}
+
+/// Return the archive filename for a server backup taken at `when`
+pub fn backup_filename<Tz: TimeZone>(slug: &str, when: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    return format!("{}_{}.zip", slug, when.format("%Y%m%d-%H%M%S"));
+}
+
+/// Return the existing backups for a server in `dir`, oldest first
+pub fn list_backups(dir: &Path, slug: &str) -> io::Result<Vec<PathBuf>> {
+    let prefix = format!("{}_", slug);
+    let mut ret: Vec<PathBuf> = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_backup = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => is_backup_name(n, &prefix),
+            None => false,
+        };
+        if is_backup {
+            ret.push(path);
+        }
+    }
+
+    // The timestamp format sorts lexically
+    ret.sort();
+
+    return Ok(ret);
+}
+
+/// Remove all but the `keep` newest backups for a server and return the
+/// paths that were removed
+pub fn prune_backups(
+    dir: &Path,
+    slug: &str,
+    keep: usize,
+) -> io::Result<Vec<PathBuf>> {
+    let backups = list_backups(dir, slug)?;
+    if backups.len() <= keep {
+        return Ok(vec![]);
+    }
+
+    let to_remove = backups[..backups.len() - keep].to_vec();
+    for path in &to_remove {
+        fs::remove_file(path)?;
+    }
+
+    return Ok(to_remove);
+}
+
+/// Build the `import` selector for a teleporter restore from a list of
+/// category names.  An empty list means import everything
+pub fn import_selector(categories: &[&str]) -> Value {
+    let all = categories.is_empty();
+    let want = |c: &str| all || categories.contains(&c);
+
+    let mut gravity = json!({});
+    for g in GRAVITY_CATEGORIES {
+        gravity[*g] = json!(want(g));
+    }
+
+    return json!({
+        "config": want("config"),
+        "dhcp_leases": want("dhcp_leases"),
+        "gravity": gravity,
+    });
+}
+
+/// A backup name is the server prefix followed by exactly the timestamp
+/// format used in `backup_filename()`
+fn is_backup_name(name: &str, prefix: &str) -> bool {
+    let rest = match name.strip_prefix(prefix) {
+        Some(r) => r,
+        None => return false,
+    };
+    let stamp = match rest.strip_suffix(".zip") {
+        Some(s) => s,
+        None => return false,
+    };
+
+    return stamp.len() == 15
+        && stamp.chars().enumerate().all(|(i, c)| {
+            if i == 8 { c == '-' } else { c.is_ascii_digit() }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_server_slug() {
+        assert_eq!(server_slug("http://pihole.local/"), "pihole.local");
+        assert_eq!(
+            server_slug("https://10.0.0.2:8443/admin"),
+            "10.0.0.2_8443_admin",
+        );
+    }
+
+    #[test]
+    fn test_backup_filename() {
+        let when = Utc.with_ymd_and_hms(2024, 3, 9, 4, 5, 6).unwrap();
+        assert_eq!(
+            backup_filename("pihole.local", &when),
+            "pihole.local_20240309-040506.zip",
+        );
+    }
+
+    #[test]
+    fn test_is_backup_name() {
+        assert!(is_backup_name("pi_20240309-040506.zip", "pi_"));
+        // A different server that shares a prefix must not match
+        assert!(!is_backup_name("pi_2_20240309-040506.zip", "pi_"));
+        assert!(!is_backup_name("pi_20240309-040506.zip.tmp", "pi_"));
+    }
+
+    #[test]
+    fn test_import_selector() {
+        let all = import_selector(&[]);
+        assert_eq!(all["config"], json!(true));
+        assert_eq!(all["gravity"]["adlist"], json!(true));
+
+        let some = import_selector(&["config", "adlist"]);
+        assert_eq!(some["config"], json!(true));
+        assert_eq!(some["dhcp_leases"], json!(false));
+        assert_eq!(some["gravity"]["adlist"], json!(true));
+        assert_eq!(some["gravity"]["group"], json!(false));
+    }
+}
@@ -0,0 +1,287 @@
+#![allow(dead_code)]
+
+use serde_json::Value;
+use super::messages;
+use super::version::Versions;
+
+/// The names accepted for `check --checks`
+pub const CHECKS: &[&str] = &["reachable", "blocking", "gravity", "version",
+    "queries", "messages"];
+
+/// The Nagios plugin states, ordered from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Ok,
+    Unknown,
+    Warning,
+    Critical,
+}
+
+impl State {
+    /// The plugin exit code for the state
+    pub fn code(&self) -> i32 {
+        return match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        };
+    }
+
+    pub fn label(&self) -> &'static str {
+        return match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        };
+    }
+}
+
+/// The configurable limits for the checks
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Warn when gravity is older than this many days
+    pub gravity_days: f64,
+    /// The minutes to count the recent queries over
+    pub window_mins: i64,
+    /// Critical when a server got fewer queries than this in the window
+    pub min_queries: i64,
+}
+
+/// The API responses needed for the checks on one server.  Anything that
+/// wasn't fetched, or failed, is None
+#[derive(Clone, Debug, Default)]
+pub struct ServerData {
+    pub name: String,
+    pub reachable: bool,
+    pub blocking: Option<Value>,
+    pub summary: Option<Value>,
+    pub version: Option<Value>,
+    pub queries: Option<Value>,
+    pub messages: Option<Value>,
+}
+
+/// The outcome of the checks for one server
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub state: State,
+    pub problems: Vec<String>,
+    pub perfdata: Vec<String>,
+}
+
+impl Outcome {
+    fn fail(&mut self, state: State, problem: String) {
+        self.state = self.state.max(state);
+        self.problems.push(problem);
+    }
+
+    /// The data for a check is missing, so its result is unknown
+    fn unavailable(&mut self, what: &str) {
+        self.fail(State::Unknown, format!("{} unavailable", what));
+    }
+}
+
+/// Run the enabled checks against one server.  Blocking left disabled
+/// without a timer, no recent queries and an unreachable server are
+/// critical.  Blocking disabled on a timer, old gravity, an outdated
+/// version and FTL messages are warnings.  A check without the data to
+/// decide is unknown
+pub fn evaluate(d: &ServerData, checks: &[&str], limits: &Limits, now: i64) -> Outcome {
+    let mut ret = Outcome { state: State::Ok, problems: vec![], perfdata: vec![] };
+    let on = |c: &str| checks.contains(&c);
+    let perf_name = |k: &str| format!("{}_{}", d.name, k);
+
+    if !d.reachable {
+        if on("reachable") {
+            ret.fail(State::Critical, "unreachable".to_string());
+        } else {
+            ret.unavailable("status");
+        }
+        return ret;
+    }
+
+    if on("blocking") {
+        match d.blocking.as_ref().and_then(|b| b["blocking"].as_str()) {
+            Some("enabled") => ret.perfdata.push(
+                format!("{}=1;;;0;1", perf_name("blocking"))),
+            Some(state) => {
+                ret.perfdata.push(
+                    format!("{}=0;;;0;1", perf_name("blocking")));
+                match d.blocking.as_ref().and_then(|b| b["timer"].as_f64()) {
+                    Some(t) => ret.fail(State::Warning,
+                        format!("blocking {} for {:.0}s", state, t)),
+                    None => ret.fail(State::Critical,
+                        format!("blocking {}", state)),
+                }
+            },
+            None => ret.unavailable("blocking status"),
+        }
+    }
+
+    if on("gravity") {
+        let updated = d.summary.as_ref()
+            .and_then(|s| s["gravity"]["last_update"].as_i64())
+            .filter(|t| *t > 0);
+        match updated {
+            Some(t) => {
+                let days = (now - t) as f64 / 86400.0;
+                ret.perfdata.push(format!("{}={:.2};{}",
+                    perf_name("gravity_age_days"), days, limits.gravity_days));
+                if days > limits.gravity_days {
+                    ret.fail(State::Warning,
+                        format!("gravity is {:.1} days old", days));
+                }
+            },
+            None => ret.unavailable("gravity update time"),
+        }
+    }
+
+    if on("version") {
+        match d.version.as_ref().map(Versions::from_value) {
+            Some(Ok(v)) => {
+                let behind: Vec<&str> = super::version::COMPONENTS.iter()
+                    .filter(|c| v.get(c).map(|x| x.is_outdated())
+                        .unwrap_or(false))
+                    .cloned()
+                    .collect();
+                if !behind.is_empty() {
+                    ret.fail(State::Warning,
+                        format!("update available for {}", behind.join(", ")));
+                }
+            },
+            _ => ret.unavailable("version info"),
+        }
+    }
+
+    if on("queries") {
+        match d.queries.as_ref().and_then(|q| q["recordsFiltered"].as_i64()) {
+            Some(n) => {
+                ret.perfdata.push(format!("{}={};;{}:", perf_name("queries"), n,
+                    limits.min_queries));
+                if n < limits.min_queries {
+                    ret.fail(State::Critical, format!("{} queries in {}m", n,
+                        limits.window_mins));
+                }
+            },
+            None => ret.unavailable("query count"),
+        }
+    }
+
+    if on("messages") {
+        match d.messages.as_ref().map(messages::parse_messages) {
+            Some(Ok(m)) => {
+                ret.perfdata.push(format!("{}={}", perf_name("messages"),
+                    m.len()));
+                if !m.is_empty() {
+                    ret.fail(State::Warning,
+                        format!("{} FTL messages", m.len()));
+                }
+            },
+            _ => ret.unavailable("FTL messages"),
+        }
+    }
+
+    return ret;
+}
+
+/// Combine the outcomes into the overall state and the plugin output line,
+/// ex: "PIHOLE CRITICAL - pi1: blocking disabled | pi1_blocking=0;;;0;1"
+pub fn status_line(outcomes: &[(String, Outcome)]) -> (State, String) {
+    let state = outcomes.iter().map(|(_, o)| o.state).max()
+        .unwrap_or(State::Unknown);
+    let problems: Vec<String> = outcomes.iter()
+        .filter(|(_, o)| !o.problems.is_empty())
+        .map(|(name, o)| format!("{}: {}", name, o.problems.join(", ")))
+        .collect();
+    let text = if outcomes.is_empty() {
+        "no servers configured".to_string()
+    } else if problems.is_empty() {
+        format!("{} servers OK", outcomes.len())
+    } else {
+        problems.join("; ")
+    };
+    let perf: Vec<String> = outcomes.iter()
+        .flat_map(|(_, o)| o.perfdata.iter().cloned())
+        .collect();
+
+    let mut line = format!("PIHOLE {} - {}", state.label(), text);
+    if !perf.is_empty() {
+        line.push_str(&format!(" | {}", perf.join(" ")));
+    }
+
+    return (state, line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits() -> Limits {
+        return Limits { gravity_days: 7.0, window_mins: 5, min_queries: 1 };
+    }
+
+    fn data() -> ServerData {
+        return ServerData {
+            name: "pi1".to_string(),
+            reachable: true,
+            blocking: Some(json!({"blocking": "enabled", "timer": null})),
+            summary: Some(json!({"gravity": {"last_update": 1000}})),
+            version: Some(json!({"version": {
+                "core": {"local": {"version": "v6.0.5"},
+                    "remote": {"version": "v6.0.5"}},
+            }})),
+            queries: Some(json!({"recordsFiltered": 42})),
+            messages: Some(json!({"messages": []})),
+        };
+    }
+
+    #[test]
+    fn test_evaluate_ok() {
+        let o = evaluate(&data(), CHECKS, &limits(), 1000 + 86400);
+        assert_eq!(o.state, State::Ok);
+        assert!(o.problems.is_empty());
+        assert_eq!(o.perfdata, vec!["pi1_blocking=1;;;0;1",
+            "pi1_gravity_age_days=1.00;7", "pi1_queries=42;;1:",
+            "pi1_messages=0"]);
+    }
+
+    #[test]
+    fn test_evaluate_blocking() {
+        let mut d = data();
+        d.blocking = Some(json!({"blocking": "disabled", "timer": 30.0}));
+        let o = evaluate(&d, &["blocking"], &limits(), 0);
+        assert_eq!(o.state, State::Warning);
+        d.blocking = Some(json!({"blocking": "disabled", "timer": null}));
+        let o = evaluate(&d, &["blocking"], &limits(), 0);
+        assert_eq!(o.state, State::Critical);
+        assert_eq!(o.problems, vec!["blocking disabled"]);
+    }
+
+    #[test]
+    fn test_evaluate_unreachable_and_unknown() {
+        let mut d = data();
+        d.queries = None;
+        assert_eq!(evaluate(&d, &["queries"], &limits(), 0).state,
+            State::Unknown);
+        d.reachable = false;
+        assert_eq!(evaluate(&d, CHECKS, &limits(), 0).state, State::Critical);
+    }
+
+    #[test]
+    fn test_status_line() {
+        let ok = evaluate(&data(), &["blocking"], &limits(), 0);
+        let mut d = data();
+        d.name = "pi2".to_string();
+        d.messages = Some(json!({"messages": [{"id": 1, "timestamp": 1.0,
+            "type": "RATE_LIMIT", "plain": "x"}]}));
+        let warn = evaluate(&d, &["messages"], &limits(), 0);
+        let (state, line) = status_line(&[("pi1".to_string(), ok),
+            ("pi2".to_string(), warn)]);
+        assert_eq!(state, State::Warning);
+        assert_eq!(line, "PIHOLE WARNING - pi2: 1 FTL messages | \
+            pi1_blocking=1;;;0;1 pi2_messages=1");
+        assert_eq!(State::Unknown.code(), 3);
+    }
+}
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+use std::io::IsTerminal;
+
+/// The ANSI colors used for terminal output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        return match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Blue => "34",
+            Color::Magenta => "35",
+            Color::Cyan => "36",
+        };
+    }
+
+    /// A stable color for the Nth item of a list, ex: a server
+    pub fn nth(n: usize) -> Self {
+        let colors = [
+            Color::Cyan,
+            Color::Magenta,
+            Color::Blue,
+            Color::Green,
+            Color::Yellow,
+        ];
+
+        return colors[n % colors.len()];
+    }
+}
+
+/// Return true if stdout is a terminal, so colors should be used
+pub fn stdout_is_tty() -> bool {
+    return std::io::stdout().is_terminal();
+}
+
+/// Wrap a string in the escape codes for a color, or return it as is if
+/// colors are disabled
+pub fn paint(s: &str, color: Color, enabled: bool) -> String {
+    if !enabled {
+        return s.to_string();
+    }
+
+    return format!("\x1b[{}m{}\x1b[0m", color.code(), s);
+}
@@ -0,0 +1,213 @@
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+/// A single DHCP lease as returned by the API
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Lease {
+    pub expires: i64,
+    pub name: String,
+    pub hwaddr: String,
+    pub ip: String,
+    #[serde(default)]
+    pub clientid: String,
+}
+
+/// A lease merged across servers, with the servers that hold it
+#[derive(Serialize, Clone, Debug)]
+pub struct MergedLease {
+    #[serde(flatten)]
+    pub lease: Lease,
+    pub servers: Vec<String>,
+    pub conflict: bool,
+}
+
+/// Parse the leases out of a `dhcp/leases` response
+pub fn parse_leases(resp: &Value) -> Result<Vec<Lease>, String> {
+    return serde_json::from_value::<Vec<Lease>>(resp["leases"].clone())
+        .map_err(|e| format!("Failed to parse leases: {}", e));
+}
+
+/// Merge the leases from all servers.  Identical leases (same IP and MAC)
+/// held by more than one server are combined into one entry, and the
+/// result is sorted by IP
+pub fn merge_leases(leases: &[(String, Lease)]) -> Vec<MergedLease> {
+    let mut merged: BTreeMap<(String, String), MergedLease> = BTreeMap::new();
+    for (server, lease) in leases {
+        let key = (lease.ip.clone(), lease.hwaddr.to_lowercase());
+        let entry = merged.entry(key).or_insert_with(|| MergedLease {
+            lease: lease.clone(),
+            servers: vec![],
+            conflict: false,
+        });
+        if !entry.servers.contains(server) {
+            entry.servers.push(server.clone());
+        }
+        // Keep the lease that expires last
+        if lease.expires > entry.lease.expires {
+            entry.lease.expires = lease.expires;
+        }
+    }
+
+    let conflicts = find_conflicts(leases);
+    let mut ret: Vec<MergedLease> = merged.into_values()
+        .map(|mut m| {
+            m.conflict = conflicts.iter().any(|c| {
+                c.ips.contains(&m.lease.ip)
+                    || c.hwaddrs.contains(&m.lease.hwaddr.to_lowercase())
+            });
+            m
+        })
+        .collect();
+    ret.sort_by_key(|m| ip_sort_key(&m.lease.ip));
+
+    return ret;
+}
+
+/// A duplicate assignment between servers
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub message: String,
+    pub ips: Vec<String>,
+    pub hwaddrs: Vec<String>,
+}
+
+/// Find the IPs leased to more than one MAC, and the MACs that hold leases
+/// from more than one server.  Both mean that more than one server is
+/// handing out addresses on the same network
+pub fn find_conflicts(leases: &[(String, Lease)]) -> Vec<Conflict> {
+    let mut by_ip: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mut by_mac: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    let mut mac_ips: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+
+    for (server, lease) in leases {
+        let mac = lease.hwaddr.to_lowercase();
+        by_ip.entry(&lease.ip).or_default().insert(mac.clone());
+        by_mac.entry(mac.clone()).or_default().insert(server);
+        mac_ips.entry(mac).or_default().insert(&lease.ip);
+    }
+
+    let mut ret: Vec<Conflict> = vec![];
+    for (ip, macs) in &by_ip {
+        if macs.len() > 1 {
+            let macs: Vec<String> = macs.iter().cloned().collect();
+            ret.push(Conflict {
+                message: format!("{} is leased to multiple MACs: {}",
+                    ip, macs.join(", ")),
+                ips: vec![ip.to_string()],
+                hwaddrs: macs,
+            });
+        }
+    }
+    for (mac, servers) in &by_mac {
+        if servers.len() > 1 {
+            let servers: Vec<&str> = servers.iter().cloned().collect();
+            ret.push(Conflict {
+                message: format!("{} has leases from multiple servers: {}",
+                    mac, servers.join(", ")),
+                ips: mac_ips[mac].iter().map(|s| s.to_string()).collect(),
+                hwaddrs: vec![mac.clone()],
+            });
+        }
+    }
+
+    return ret;
+}
+
+/// Format the named leases as /etc/hosts lines
+pub fn to_hosts(leases: &[MergedLease]) -> String {
+    let mut ret = String::new();
+    let mut seen: BTreeSet<&str> = BTreeSet::new();
+    for m in leases {
+        let name = m.lease.name.trim();
+        if name.is_empty() || name == "*" || !seen.insert(&m.lease.ip) {
+            continue;
+        }
+        ret.push_str(&format!("{}\t{}\n", m.lease.ip, name));
+    }
+
+    return ret;
+}
+
+/// Parse the IP of a lease to delete.  This goes into the API path, so
+/// anything that isn't a plain IP address is rejected
+pub fn parse_lease_ip(ip: &str) -> Result<IpAddr, String> {
+    return ip.trim().parse::<IpAddr>()
+        .map_err(|_| format!("'{}' is not a valid IP address", ip));
+}
+
+/// Sort IPv4 addresses numerically, and anything else after them
+fn ip_sort_key(ip: &str) -> (u8, Vec<u32>, String) {
+    let parts: Vec<u32> = ip.split('.').filter_map(|p| p.parse().ok()).collect();
+    if parts.len() == 4 {
+        return (0, parts, "".to_string());
+    }
+
+    return (1, vec![], ip.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease(ip: &str, mac: &str, name: &str) -> Lease {
+        return Lease {
+            expires: 0,
+            name: name.to_string(),
+            hwaddr: mac.to_string(),
+            ip: ip.to_string(),
+            clientid: "".to_string(),
+        };
+    }
+
+    #[test]
+    fn test_merge_no_conflicts() {
+        let leases = vec![
+            ("a".to_string(), lease("10.0.0.10", "aa:bb", "laptop")),
+            ("a".to_string(), lease("10.0.0.9", "cc:dd", "phone")),
+        ];
+        let merged = merge_leases(&leases);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].lease.ip, "10.0.0.9");
+        assert!(find_conflicts(&leases).is_empty());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let leases = vec![
+            ("a".to_string(), lease("10.0.0.10", "aa:bb", "laptop")),
+            ("b".to_string(), lease("10.0.0.10", "cc:dd", "phone")),
+            ("b".to_string(), lease("10.0.0.11", "AA:BB", "laptop")),
+        ];
+        let conflicts = find_conflicts(&leases);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].ips, vec!["10.0.0.10"]);
+        assert_eq!(conflicts[1].hwaddrs, vec!["aa:bb"]);
+
+        let merged = merge_leases(&leases);
+        assert!(merged.iter().all(|m| m.conflict));
+    }
+
+    #[test]
+    fn test_to_hosts() {
+        let leases = vec![
+            ("a".to_string(), lease("10.0.0.10", "aa:bb", "laptop")),
+            ("a".to_string(), lease("10.0.0.11", "cc:dd", "*")),
+        ];
+        assert_eq!(to_hosts(&merge_leases(&leases)), "10.0.0.10\tlaptop\n");
+    }
+
+    #[test]
+    fn test_parse_lease_ip() {
+        assert_eq!(parse_lease_ip("10.0.0.10").unwrap().to_string(),
+            "10.0.0.10");
+        assert_eq!(parse_lease_ip("fd00::10").unwrap().to_string(), "fd00::10");
+        assert!(parse_lease_ip("10.0.0.10/../../config").is_err());
+        assert!(parse_lease_ip("10.0.0.10?force=1").is_err());
+        assert_eq!(parse_lease_ip("laptop").err().unwrap(),
+            "'laptop' is not a valid IP address");
+    }
+}
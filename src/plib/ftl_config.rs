@@ -0,0 +1,113 @@
+#![allow(dead_code)]
+
+use serde_json::{self, Value};
+
+/// Walk a config tree (the `config` object returned by the API) following a
+/// dotted path, ex: "dns.upstreams"
+pub fn lookup<'a>(root: &'a Value, key: &str) -> Option<&'a Value> {
+    let mut cur = root;
+    for part in key.split('.') {
+        cur = cur.get(part)?;
+    }
+
+    return Some(cur);
+}
+
+/// A detailed setting is the leaf of the config tree when it was requested
+/// with `detailed=true`
+pub fn is_setting(detailed: &Value) -> bool {
+    return detailed.get("value").is_some() && detailed.get("flags").is_some();
+}
+
+/// Return true if changing this (detailed) setting makes FTL restart
+pub fn needs_restart(detailed: &Value) -> bool {
+    return detailed["flags"]["restart_dnsmasq"].as_bool().unwrap_or(false);
+}
+
+/// Return a short name for the JSON type of a value for error messages
+pub fn type_name(v: &Value) -> &'static str {
+    return match v {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+}
+
+/// Parse a raw command line value into JSON with the same type as the
+/// current value of the setting.  Strings don't need to be quoted
+pub fn parse_value(raw: &str, current: &Value) -> Result<Value, String> {
+    if let Value::String(_) = current {
+        // Accept a JSON quoted string too, but fall back to the raw text
+        return match serde_json::from_str::<Value>(raw) {
+            Ok(Value::String(s)) => Ok(Value::String(s)),
+            _ => Ok(Value::String(raw.to_string())),
+        };
+    }
+
+    let new = match serde_json::from_str::<Value>(raw) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(format!("'{}' is not valid JSON: {}", raw, e));
+        },
+    };
+
+    if type_name(&new) != type_name(current) {
+        return Err(format!("expected a {} but got a {}",
+            type_name(current), type_name(&new)));
+    }
+
+    // Check the element types for arrays against an existing element
+    if let (Value::Array(cur), Value::Array(vals)) = (current, &new) {
+        if let Some(first) = cur.first() {
+            for v in vals {
+                if type_name(v) != type_name(first) {
+                    return Err(format!("expected an array of {} but got \
+                        an element of type {}",
+                        type_name(first), type_name(v)));
+                }
+            }
+        }
+    }
+
+    return Ok(new);
+}
+
+/// Format a value on a single line for summaries
+pub fn short(v: &Value) -> String {
+    return match v {
+        Value::String(s) => s.clone(),
+        _ => v.to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_lookup() {
+        let root = json!({"dns": {"upstreams": ["1.1.1.1"], "port": 53}});
+        assert_eq!(lookup(&root, "dns.port"), Some(&json!(53)));
+        assert_eq!(lookup(&root, "dns.upstreams"), Some(&json!(["1.1.1.1"])));
+        assert!(lookup(&root, "dns.nope").is_none());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("pi.hole", &json!("x")), Ok(json!("pi.hole")));
+        assert_eq!(parse_value("\"a b\"", &json!("x")), Ok(json!("a b")));
+        assert_eq!(parse_value("true", &json!(false)), Ok(json!(true)));
+        assert_eq!(parse_value("5353", &json!(53)), Ok(json!(5353)));
+        assert_eq!(
+            parse_value("[\"9.9.9.9\"]", &json!(["1.1.1.1"])),
+            Ok(json!(["9.9.9.9"])),
+        );
+        assert!(parse_value("yes", &json!(false)).is_err());
+        assert!(parse_value("\"53\"", &json!(53)).is_err());
+        assert!(parse_value("[1]", &json!(["1.1.1.1"])).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+#![allow(dead_code)]
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// The overall state of a server, ordered from best to worst
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Ok,
+    Warning,
+    Critical,
+}
+
+/// The limits above which a server is flagged as a warning
+#[derive(Clone, Debug)]
+pub struct Thresholds {
+    /// The 1 minute load average per CPU
+    pub load: f64,
+    /// The percentage of RAM used
+    pub mem_pct: f64,
+    /// The CPU temperature in the unit the server reports
+    pub temp: f64,
+    /// The age of gravity in days
+    pub gravity_days: f64,
+}
+
+/// The health of one server, pulled from the info and summary endpoints.
+/// Anything the server didn't report is None
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Health {
+    pub server: String,
+    pub reachable: bool,
+    pub uptime: Option<i64>,
+    pub load: Option<f64>,
+    pub nprocs: Option<i64>,
+    pub mem_pct: Option<f64>,
+    pub cpu_temp: Option<f64>,
+    pub temp_unit: Option<String>,
+    pub ftl_pid: Option<i64>,
+    pub privacy_level: Option<i64>,
+    pub db_size: Option<i64>,
+    pub gravity_updated: Option<i64>,
+    pub clients_active: Option<i64>,
+    pub clients_total: Option<i64>,
+}
+
+impl Health {
+    /// Build the health from the API responses for `info/system`,
+    /// `info/ftl`, `info/database`, `info/sensors` and `stats/summary`
+    pub fn from_values(
+        server: &str,
+        system: Option<&Value>,
+        ftl: Option<&Value>,
+        database: Option<&Value>,
+        sensors: Option<&Value>,
+        summary: Option<&Value>,
+    ) -> Self {
+        let (system, ftl, database, sensors, summary) = (no_error(system),
+            no_error(ftl), no_error(database), no_error(sensors),
+            no_error(summary));
+
+        let mut ret = Self {
+            server: server.to_string(),
+            ..Default::default()
+        };
+        ret.reachable = system.is_some() || ftl.is_some() || summary.is_some();
+
+        if let Some(v) = system {
+            let sys = &v["system"];
+            ret.uptime = sys["uptime"].as_i64();
+            ret.load = sys["cpu"]["load"]["raw"][0].as_f64();
+            ret.nprocs = sys["cpu"]["nprocs"].as_i64();
+            ret.mem_pct = sys["memory"]["ram"]["%used"].as_f64();
+        }
+        if let Some(v) = ftl {
+            let f = &v["ftl"];
+            ret.ftl_pid = f["pid"].as_i64();
+            ret.privacy_level = f["privacy_level"].as_i64();
+            ret.clients_active = f["clients"]["active"].as_i64();
+            ret.clients_total = f["clients"]["total"].as_i64();
+        }
+        if let Some(v) = database {
+            ret.db_size = v["size"].as_i64();
+        }
+        if let Some(v) = sensors {
+            ret.cpu_temp = v["sensors"]["cpu_temp"].as_f64();
+            ret.temp_unit = v["sensors"]["unit"].as_str().map(|s| s.to_string());
+        }
+        if let Some(v) = summary {
+            ret.gravity_updated = v["gravity"]["last_update"].as_i64();
+        }
+
+        return ret;
+    }
+
+    /// Return the reasons this server is over any of the thresholds, along
+    /// with the overall level
+    pub fn evaluate(&self, limits: &Thresholds, now: i64) -> (Level, Vec<String>) {
+        if !self.reachable {
+            return (Level::Critical, vec!["unreachable".to_string()]);
+        }
+
+        let mut reasons: Vec<String> = vec![];
+        if let Some(load) = self.load {
+            let per_cpu = load / self.nprocs.unwrap_or(1).max(1) as f64;
+            if per_cpu > limits.load {
+                reasons.push(format!("load {:.2} per cpu", per_cpu));
+            }
+        }
+        if let Some(m) = self.mem_pct {
+            if m > limits.mem_pct {
+                reasons.push(format!("memory {:.1}%", m));
+            }
+        }
+        if let Some(t) = self.cpu_temp {
+            if t > limits.temp {
+                reasons.push(format!("cpu temp {:.1}", t));
+            }
+        }
+        if let Some(age) = self.gravity_age_days(now) {
+            if age > limits.gravity_days {
+                reasons.push(format!("gravity {:.0} days old", age));
+            }
+        }
+        if self.ftl_pid.is_none() {
+            reasons.push("FTL info unavailable".to_string());
+        }
+
+        let level = if reasons.is_empty() { Level::Ok } else { Level::Warning };
+        return (level, reasons);
+    }
+
+    pub fn gravity_age_days(&self, now: i64) -> Option<f64> {
+        return self.gravity_updated
+            .filter(|t| *t > 0)
+            .map(|t| (now - t) as f64 / 86400.0);
+    }
+}
+
+/// An error response (ex: not authenticated) is as good as no response
+fn no_error(v: Option<&Value>) -> Option<&Value> {
+    return v.filter(|x| x.get("error").is_none());
+}
+
+/// Format a number of seconds as a short duration, ex: "3d 4h"
+pub fn fmt_duration(secs: i64) -> String {
+    let days = secs / 86400;
+    let hours = (secs % 86400) / 3600;
+    let mins = (secs % 3600) / 60;
+
+    if days > 0 {
+        return format!("{}d {}h", days, hours);
+    } else if hours > 0 {
+        return format!("{}h {}m", hours, mins);
+    }
+
+    return format!("{}m", mins);
+}
+
+/// Format a number of bytes with a binary unit suffix, ex: "12.3M"
+pub fn fmt_bytes(bytes: i64) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut val = bytes as f64;
+    let mut i = 0;
+    while val >= 1024.0 && i < units.len() - 1 {
+        val /= 1024.0;
+        i += 1;
+    }
+
+    if i == 0 {
+        return format!("{}{}", bytes, units[0]);
+    }
+
+    return format!("{:.1}{}", val, units[i]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits() -> Thresholds {
+        return Thresholds {
+            load: 1.0,
+            mem_pct: 90.0,
+            temp: 70.0,
+            gravity_days: 7.0,
+        };
+    }
+
+    #[test]
+    fn test_from_values_and_evaluate() {
+        let system = json!({"system": {"uptime": 90000,
+            "memory": {"ram": {"%used": 95.5}},
+            "cpu": {"nprocs": 4, "load": {"raw": [2.0, 1.0, 1.0]}}}});
+        let ftl = json!({"ftl": {"pid": 42, "privacy_level": 0,
+            "clients": {"active": 3, "total": 9}}});
+        let summary = json!({"gravity": {"last_update": 0}});
+        let h = Health::from_values("a", Some(&system), Some(&ftl), None,
+            None, Some(&summary));
+
+        assert_eq!(h.uptime, Some(90000));
+        assert_eq!(h.ftl_pid, Some(42));
+        assert_eq!(h.clients_total, Some(9));
+        // A gravity that was never updated has no age
+        assert_eq!(h.gravity_age_days(100), None);
+
+        let (level, reasons) = h.evaluate(&limits(), 0);
+        assert_eq!(level, Level::Warning);
+        assert_eq!(reasons, vec!["memory 95.5%"]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let h = Health::from_values("a", None, None, None, None, None);
+        assert_eq!(h.evaluate(&limits(), 0).0, Level::Critical);
+
+        let err = json!({"error": {"key": "unauthorized"}});
+        let h = Health::from_values("a", Some(&err), Some(&err), None, None,
+            Some(&err));
+        assert!(!h.reachable);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(fmt_duration(90000), "1d 1h");
+        assert_eq!(fmt_duration(3700), "1h 1m");
+        assert_eq!(fmt_bytes(512), "512B");
+        assert_eq!(fmt_bytes(5 * 1024 * 1024), "5.0M");
+    }
+}
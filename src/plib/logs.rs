@@ -0,0 +1,49 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+/// The logs that can be read through the API
+pub const LOG_NAMES: &[&str] = &["dnsmasq", "ftl", "webserver"];
+
+/// A single log line as returned by the API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogLine {
+    pub timestamp: f64,
+    pub message: String,
+    #[serde(default)]
+    pub prio: Option<String>,
+}
+
+/// Parse a `logs/<name>` response into its lines and the cursor to pass
+/// as `nextID` to only get newer lines next time
+pub fn parse_log(resp: &Value) -> Result<(Vec<LogLine>, Option<i64>), String> {
+    let lines = serde_json::from_value::<Vec<LogLine>>(resp["log"].clone())
+        .map_err(|e| format!("Failed to parse log lines: {}", e))?;
+
+    return Ok((lines, resp["nextID"].as_i64()));
+}
+
+/// Return the last `n` lines
+pub fn tail(lines: Vec<LogLine>, n: usize) -> Vec<LogLine> {
+    let skip = lines.len().saturating_sub(n);
+    return lines.into_iter().skip(skip).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_log() {
+        let resp = json!({"log": [
+            {"timestamp": 1.5, "message": "query[A] pi-hole.net", "prio": null},
+            {"timestamp": 2.0, "message": "reply pi-hole.net", "prio": "INFO"},
+        ], "nextID": 42, "pid": 1, "file": "/var/log/pihole/pihole.log"});
+        let (lines, next) = parse_log(&resp).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(next, Some(42));
+        assert_eq!(tail(lines, 1)[0].message, "reply pi-hole.net");
+    }
+}
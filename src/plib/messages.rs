@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+/// A FTL diagnosis message as returned by the API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Message {
+    pub id: i64,
+    pub timestamp: f64,
+    #[serde(rename = "type")]
+    pub mtype: String,
+    pub plain: String,
+}
+
+/// Parse the messages out of an `info/messages` response
+pub fn parse_messages(resp: &Value) -> Result<Vec<Message>, String> {
+    return serde_json::from_value::<Vec<Message>>(resp["messages"].clone())
+        .map_err(|e| format!("Failed to parse messages: {}", e));
+}
+
+/// Parse a `--since` value into a unix timestamp.  This is either a
+/// timestamp, or a duration before `now` like "90s", "30m", "12h" or "2d"
+pub fn parse_since(since: &str, now: i64) -> Result<i64, String> {
+    let s = since.trim();
+    if let Ok(ts) = s.parse::<i64>() {
+        return Ok(ts);
+    }
+
+    let err = || format!("'{}' is not a timestamp or a duration (ex: 2h)", s);
+    let unit = s.chars().last().ok_or_else(err)?;
+    let mult = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 604800,
+        _ => return Err(err()),
+    };
+    let num = s[..s.len() - 1].parse::<i64>().map_err(|_| err())?;
+
+    return Ok(now - num * mult);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_messages() {
+        let resp = json!({"messages": [{"id": 4, "timestamp": 1700000000.5,
+            "type": "RATE_LIMIT", "plain": "Client rate-limited",
+            "html": "<b>Client</b>"}]});
+        let msgs = parse_messages(&resp).unwrap();
+        assert_eq!(msgs[0].id, 4);
+        assert_eq!(msgs[0].mtype, "RATE_LIMIT");
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1700000000", 0), Ok(1700000000));
+        assert_eq!(parse_since("90s", 1000), Ok(910));
+        assert_eq!(parse_since("2h", 10000), Ok(2800));
+        assert_eq!(parse_since("1d", 86400), Ok(0));
+        assert!(parse_since("2x", 0).is_err());
+        assert!(parse_since("h", 0).is_err());
+    }
+}
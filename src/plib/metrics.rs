@@ -0,0 +1,236 @@
+#![allow(dead_code)]
+
+use serde_json::Value;
+use super::pihole::Pihole;
+
+/// Everything scraped from one server for the exporter.  A failed call
+/// leaves its part empty
+#[derive(Clone, Debug, Default)]
+pub struct Scrape {
+    pub server: String,
+    pub blocking: Option<Value>,
+    pub summary: Option<Value>,
+    pub upstreams: Option<Value>,
+    pub query_types: Option<Value>,
+    pub clients: Option<Value>,
+}
+
+impl Scrape {
+    /// Scrape a server.  Error responses, ex: from an expired session, are
+    /// dropped like failed calls
+    pub fn fetch(s: &Pihole, top_clients: usize) -> Self {
+        let ok = |v: Option<Value>| v.filter(|x| x.get("error").is_none());
+
+        return Self {
+            server: s.base_url.clone(),
+            blocking: ok(s.blocking()),
+            summary: ok(s.summary()),
+            upstreams: ok(s.get_upstreams()),
+            query_types: ok(s.get_query_types()),
+            clients: ok(s.top_clients(Some(top_clients))),
+        };
+    }
+
+    /// The server is considered down if it didn't return its status
+    pub fn is_up(&self) -> bool {
+        return self.blocking.is_some();
+    }
+}
+
+struct Metric {
+    name: String,
+    help: String,
+    samples: Vec<(String, f64)>,
+}
+
+/// A set of gauges in the Prometheus text exposition format.  Samples for
+/// the same metric are kept together, as the format requires
+#[derive(Default)]
+pub struct Registry {
+    metrics: Vec<Metric>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Add a sample for the gauge `name`, with its labels in order
+    pub fn add(&mut self, name: &str, help: &str, labels: &[(&str, &str)], val: f64) {
+        let labels: Vec<String> = labels.iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+            .collect();
+        let sample = (labels.join(","), val);
+
+        match self.metrics.iter_mut().find(|m| m.name == name) {
+            Some(m) => m.samples.push(sample),
+            None => self.metrics.push(Metric {
+                name: name.to_string(),
+                help: help.to_string(),
+                samples: vec![sample],
+            }),
+        }
+    }
+
+    /// Add a sample if there is a numeric value for it
+    fn add_value(&mut self, name: &str, help: &str, labels: &[(&str, &str)], v: &Value) {
+        if let Some(val) = v.as_f64() {
+            self.add(name, help, labels, val);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut ret = String::new();
+        for m in &self.metrics {
+            ret.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n",
+                m.name, m.help, m.name));
+            for (labels, val) in &m.samples {
+                if labels.is_empty() {
+                    ret.push_str(&format!("{} {}\n", m.name, val));
+                } else {
+                    ret.push_str(&format!("{}{{{}}} {}\n", m.name, labels, val));
+                }
+            }
+        }
+
+        return ret;
+    }
+}
+
+/// Build the metrics for all the scraped servers, labeled by server url
+pub fn build(scrapes: &[Scrape]) -> Registry {
+    let mut reg = Registry::new();
+    for s in scrapes {
+        let server = [("server", s.server.as_str())];
+        reg.add("pihole_up", "Whether the server answered the last scrape",
+            &server, if s.is_up() { 1.0 } else { 0.0 });
+
+        if let Some(b) = &s.blocking {
+            let enabled = b["blocking"].as_str() == Some("enabled");
+            reg.add("pihole_blocking_enabled", "Whether blocking is enabled",
+                &server, if enabled { 1.0 } else { 0.0 });
+            reg.add("pihole_blocking_timer_seconds",
+                "The seconds left until blocking is re-enabled, 0 if there \
+                is no timer", &server, b["timer"].as_f64().unwrap_or(0.0));
+        }
+
+        if let Some(v) = &s.summary {
+            let q = &v["queries"];
+            for (key, name, help) in [
+                ("total", "pihole_queries", "Queries in the last 24 hours"),
+                ("blocked", "pihole_queries_blocked",
+                    "Blocked queries in the last 24 hours"),
+                ("forwarded", "pihole_queries_forwarded",
+                    "Forwarded queries in the last 24 hours"),
+                ("cached", "pihole_queries_cached",
+                    "Cached queries in the last 24 hours"),
+                ("percent_blocked", "pihole_queries_blocked_percent",
+                    "The percentage of queries blocked in the last 24 hours"),
+                ("unique_domains", "pihole_unique_domains",
+                    "Unique domains queried in the last 24 hours"),
+            ] {
+                reg.add_value(name, help, &server, &q[key]);
+            }
+            reg.add_value("pihole_clients_active", "Active clients",
+                &server, &v["clients"]["active"]);
+            reg.add_value("pihole_clients", "Clients seen", &server,
+                &v["clients"]["total"]);
+            reg.add_value("pihole_gravity_domains", "Domains on the gravity \
+                list", &server, &v["gravity"]["domains_being_blocked"]);
+            reg.add_value("pihole_gravity_last_update_timestamp_seconds",
+                "When gravity was last updated", &server,
+                &v["gravity"]["last_update"]);
+        }
+
+        if let Some(types) = s.query_types.as_ref()
+            .and_then(|v| v["types"].as_object())
+        {
+            for (t, n) in types {
+                reg.add_value("pihole_query_type_queries", "Queries by type \
+                    in the last 24 hours",
+                    &[("server", &s.server), ("type", t)], n);
+            }
+        }
+
+        let ups = s.upstreams.as_ref()
+            .and_then(|v| v["upstreams"].as_array().cloned())
+            .unwrap_or_default();
+        for u in ups {
+            let addr = match u["port"].as_i64() {
+                Some(p) if p > 0 =>
+                    format!("{}#{}", u["ip"].as_str().unwrap_or(""), p),
+                _ => u["ip"].as_str().unwrap_or("").to_string(),
+            };
+            let labels = [("server", s.server.as_str()),
+                ("upstream", addr.as_str()),
+                ("name", u["name"].as_str().unwrap_or(""))];
+            reg.add_value("pihole_upstream_queries", "Queries sent to each \
+                upstream in the last 24 hours", &labels, &u["count"]);
+            reg.add_value("pihole_upstream_response_seconds", "The average \
+                response time of each upstream", &labels,
+                &u["statistics"]["response"]);
+        }
+
+        let clients = s.clients.as_ref()
+            .and_then(|v| v["clients"].as_array().cloned())
+            .unwrap_or_default();
+        for c in clients {
+            reg.add_value("pihole_top_client_queries", "Queries from the top \
+                clients in the last 24 hours",
+                &[("server", &s.server), ("client", c["ip"].as_str().unwrap_or("")),
+                    ("name", c["name"].as_str().unwrap_or(""))],
+                &c["count"]);
+        }
+    }
+
+    return reg;
+}
+
+/// Escape a label value for the text format
+fn escape(s: &str) -> String {
+    return s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_registry_groups_samples() {
+        let mut reg = Registry::new();
+        reg.add("m", "help", &[("server", "a")], 1.0);
+        reg.add("n", "other", &[], 2.5);
+        reg.add("m", "help", &[("server", "b\"c")], 0.0);
+        assert_eq!(reg.render(), "# HELP m help\n# TYPE m gauge\n\
+            m{server=\"a\"} 1\nm{server=\"b\\\"c\"} 0\n\
+            # HELP n other\n# TYPE n gauge\nn 2.5\n");
+    }
+
+    #[test]
+    fn test_build() {
+        let scrapes = vec![
+            Scrape {
+                server: "http://a".to_string(),
+                blocking: Some(json!({"blocking": "disabled", "timer": 30.0})),
+                summary: Some(json!({"queries": {"total": 100, "blocked": 10}})),
+                query_types: Some(json!({"types": {"A": 80}})),
+                upstreams: Some(json!({"upstreams": [{"ip": "1.1.1.1",
+                    "port": 53, "name": "one", "count": 7,
+                    "statistics": {"response": 0.02}}]})),
+                ..Default::default()
+            },
+            Scrape { server: "http://b".to_string(), ..Default::default() },
+        ];
+        let out = build(&scrapes).render();
+        assert!(out.contains("pihole_up{server=\"http://a\"} 1\n"));
+        assert!(out.contains("pihole_up{server=\"http://b\"} 0\n"));
+        assert!(out.contains("pihole_blocking_enabled{server=\"http://a\"} 0\n"));
+        assert!(out.contains("pihole_queries{server=\"http://a\"} 100\n"));
+        assert!(out.contains(
+            "pihole_query_type_queries{server=\"http://a\",type=\"A\"} 80\n"));
+        assert!(out.contains("pihole_upstream_queries{server=\"http://a\",\
+            upstream=\"1.1.1.1#53\",name=\"one\"} 7\n"));
+        assert!(!out.contains("pihole_queries{server=\"http://b\"}"));
+    }
+}
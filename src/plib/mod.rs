@@ -1,3 +1,25 @@
+pub mod aggregate;
+pub mod backup;
+pub mod check;
+pub mod color;
 pub mod config;
+pub mod dhcp;
+pub mod ftl_config;
+pub mod health;
+pub mod logs;
+pub mod metrics;
+pub mod messages;
+pub mod network;
+pub mod output;
 pub mod pihole;
-pub mod web_config;
\ No newline at end of file
+pub mod push;
+pub mod tables;
+pub mod top;
+pub mod upstream;
+pub mod version;
+pub mod web_assets;
+pub mod web_auth;
+pub mod web_config;
+pub mod web_events;
+pub mod web_sessions;
+pub mod web_tls;
\ No newline at end of file
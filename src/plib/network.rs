@@ -0,0 +1,161 @@
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+/// An address a device has used, as returned by the API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceIp {
+    pub ip: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "lastSeen", default)]
+    pub last_seen: i64,
+}
+
+/// A network device as returned by the API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Device {
+    pub id: i64,
+    pub hwaddr: String,
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(rename = "firstSeen", default)]
+    pub first_seen: i64,
+    #[serde(rename = "lastQuery", default)]
+    pub last_query: i64,
+    #[serde(rename = "numQueries", default)]
+    pub num_queries: u64,
+    #[serde(rename = "macVendor", default)]
+    pub mac_vendor: Option<String>,
+    #[serde(default)]
+    pub ips: Vec<DeviceIp>,
+}
+
+/// A device merged from all the servers by its MAC address
+#[derive(Serialize, Clone, Debug)]
+pub struct MergedDevice {
+    pub hwaddr: String,
+    pub vendor: Option<String>,
+    pub names: BTreeSet<String>,
+    pub ips: BTreeSet<String>,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// The number of queries seen by each server
+    pub queries: BTreeMap<String, u64>,
+    /// The device ID on each server, used for deleting it
+    pub ids: BTreeMap<String, i64>,
+}
+
+impl MergedDevice {
+    pub fn total_queries(&self) -> u64 {
+        return self.queries.values().sum();
+    }
+}
+
+/// Parse the devices out of a `network/devices` response
+pub fn parse_devices(resp: &Value) -> Result<Vec<Device>, String> {
+    return serde_json::from_value::<Vec<Device>>(resp["devices"].clone())
+        .map_err(|e| format!("Failed to parse devices: {}", e));
+}
+
+/// Merge the device tables from all servers by MAC.  The result is sorted
+/// by the most recently seen first
+pub fn merge_devices(devices: &[(String, Device)]) -> Vec<MergedDevice> {
+    let mut merged: BTreeMap<String, MergedDevice> = BTreeMap::new();
+    for (server, dev) in devices {
+        let mac = dev.hwaddr.to_lowercase();
+        let m = merged.entry(mac.clone()).or_insert_with(|| MergedDevice {
+            hwaddr: mac,
+            vendor: None,
+            names: BTreeSet::new(),
+            ips: BTreeSet::new(),
+            first_seen: dev.first_seen,
+            last_seen: dev.last_query,
+            queries: BTreeMap::new(),
+            ids: BTreeMap::new(),
+        });
+
+        if m.vendor.is_none() {
+            m.vendor = dev.mac_vendor.clone().filter(|v| !v.is_empty());
+        }
+        // A zero means never seen, so don't let it win the minimum
+        if dev.first_seen > 0 && (m.first_seen == 0 || dev.first_seen < m.first_seen) {
+            m.first_seen = dev.first_seen;
+        }
+        m.last_seen = m.last_seen.max(dev.last_query);
+        for ip in &dev.ips {
+            m.ips.insert(ip.ip.clone());
+            m.last_seen = m.last_seen.max(ip.last_seen);
+            if let Some(n) = ip.name.as_ref().filter(|n| !n.is_empty()) {
+                m.names.insert(n.clone());
+            }
+        }
+        *m.queries.entry(server.clone()).or_insert(0) += dev.num_queries;
+        m.ids.insert(server.clone(), dev.id);
+    }
+
+    let mut ret: Vec<MergedDevice> = merged.into_values().collect();
+    ret.sort_by_key(|m| std::cmp::Reverse(m.last_seen));
+
+    return ret;
+}
+
+/// Return the devices that haven't been seen by any server since `cutoff`
+pub fn stale_devices(devices: &[MergedDevice], cutoff: i64) -> Vec<&MergedDevice> {
+    return devices.iter().filter(|d| d.last_seen < cutoff).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn device(id: i64, mac: &str, first: i64, last: i64, queries: u64) -> Device {
+        return Device {
+            id,
+            hwaddr: mac.to_string(),
+            interface: None,
+            first_seen: first,
+            last_query: last,
+            num_queries: queries,
+            mac_vendor: None,
+            ips: vec![],
+        };
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let resp = json!({"devices": [{
+            "id": 3, "hwaddr": "aa:bb", "interface": "eth0",
+            "firstSeen": 10, "lastQuery": 20, "numQueries": 5,
+            "macVendor": "Acme",
+            "ips": [{"ip": "10.0.0.2", "name": "laptop", "lastSeen": 30,
+                "nameUpdated": 30}],
+        }]});
+        let devs = parse_devices(&resp).unwrap();
+        assert_eq!(devs[0].id, 3);
+        assert_eq!(devs[0].ips[0].name.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn test_merge_devices() {
+        let devs = vec![
+            ("a".to_string(), device(1, "AA:BB", 100, 200, 5)),
+            ("b".to_string(), device(7, "aa:bb", 50, 150, 3)),
+            ("b".to_string(), device(8, "cc:dd", 10, 20, 1)),
+        ];
+        let merged = merge_devices(&devs);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].hwaddr, "aa:bb");
+        assert_eq!(merged[0].first_seen, 50);
+        assert_eq!(merged[0].last_seen, 200);
+        assert_eq!(merged[0].total_queries(), 8);
+        assert_eq!(merged[0].ids["b"], 7);
+
+        let stale = stale_devices(&merged, 100);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].hwaddr, "cc:dd");
+    }
+}
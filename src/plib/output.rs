@@ -0,0 +1,270 @@
+#![allow(dead_code)]
+
+use std::str::FromStr;
+use serde_json::{self, Map, Value, json};
+
+/// The names accepted for `--output`
+pub const FORMATS: &[&str] = &["table", "json", "ndjson", "yaml", "csv"];
+
+/// The output formats for the subcommands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Ndjson,
+    Yaml,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "yaml" => Ok(Format::Yaml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("Unknown output format: {}", s)),
+        };
+    }
+}
+
+/// The result of a command for each server, in the configured order.  A
+/// server that failed has no value
+pub type Results = Vec<(String, Option<Value>)>;
+
+/// A simple table of strings that can be rendered aligned or as CSV
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        return Self {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: vec![],
+        };
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// Render the table with the columns aligned.  Numeric columns are
+    /// right aligned
+    pub fn render(&self) -> String {
+        let ncols = self.headers.len();
+        let mut widths: Vec<usize> = self.headers.iter()
+            .map(|h| h.chars().count())
+            .collect();
+        let mut numeric: Vec<bool> = vec![!self.rows.is_empty(); ncols];
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(ncols) {
+                widths[i] = widths[i].max(cell.chars().count());
+                if !is_numeric(cell) {
+                    numeric[i] = false;
+                }
+            }
+        }
+
+        let fmt_row = |row: &Vec<String>| {
+            let cells: Vec<String> = (0..ncols)
+                .map(|i| {
+                    let cell = row.get(i).map(|c| c.as_str()).unwrap_or("");
+                    if numeric[i] {
+                        format!("{:>w$}", cell, w = widths[i])
+                    } else {
+                        format!("{:<w$}", cell, w = widths[i])
+                    }
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        };
+
+        let mut ret = fmt_row(&self.headers);
+        ret.push('\n');
+        for row in &self.rows {
+            ret.push_str(&fmt_row(row));
+            ret.push('\n');
+        }
+
+        return ret;
+    }
+
+    /// Render the table as CSV, with a header line
+    pub fn to_csv(&self) -> String {
+        let mut ret = String::new();
+        for row in std::iter::once(&self.headers).chain(self.rows.iter()) {
+            let cells: Vec<String> = row.iter().map(|c| csv_escape(c)).collect();
+            ret.push_str(&cells.join(","));
+            ret.push('\n');
+        }
+
+        return ret;
+    }
+}
+
+/// Combine the results into one JSON document keyed by server
+pub fn to_document(results: &Results) -> Value {
+    let mut doc = Map::new();
+    for (server, val) in results {
+        doc.insert(server.clone(), val.clone().unwrap_or(Value::Null));
+    }
+
+    return Value::Object(doc);
+}
+
+/// Render the results in a machine readable format.  For `Table`, the
+/// table is used if there is one, otherwise None is returned and the caller
+/// prints its own human readable output.  CSV without a table flattens the
+/// results into server, key, value rows
+pub fn render(fmt: Format, results: &Results, table: Option<&Table>) -> Option<String> {
+    return match fmt {
+        Format::Table => table.map(|t| t.render()),
+        Format::Json => Some(format!("{}\n",
+            serde_json::to_string_pretty(&to_document(results)).unwrap())),
+        Format::Ndjson => {
+            let mut ret = String::new();
+            for (server, val) in results {
+                ret.push_str(&json!({"server": server, "data": val}).to_string());
+                ret.push('\n');
+            }
+            Some(ret)
+        },
+        Format::Yaml => Some(
+            serde_yaml::to_string(&to_document(results)).unwrap()),
+        Format::Csv => match table {
+            Some(t) => Some(t.to_csv()),
+            None => {
+                let mut t = Table::new(&["server", "key", "value"]);
+                for (server, val) in results {
+                    let mut flat: Vec<(String, String)> = vec![];
+                    flatten("", val.as_ref().unwrap_or(&Value::Null), &mut flat);
+                    for (k, v) in flat {
+                        t.add_row(vec![server.clone(), k, v]);
+                    }
+                }
+                Some(t.to_csv())
+            },
+        },
+    };
+}
+
+/// Render a list of items that isn't keyed by server, ex: the leases merged
+/// from all servers.  The table is used for the table and CSV formats
+pub fn render_list(fmt: Format, items: &[Value], table: &Table) -> String {
+    return match fmt {
+        Format::Table => table.render(),
+        Format::Csv => table.to_csv(),
+        Format::Json => format!("{}\n",
+            serde_json::to_string_pretty(items).unwrap()),
+        Format::Ndjson => {
+            let mut ret = String::new();
+            for item in items {
+                ret.push_str(&item.to_string());
+                ret.push('\n');
+            }
+            ret
+        },
+        Format::Yaml => serde_yaml::to_string(items).unwrap(),
+    };
+}
+
+/// Format a JSON value for a table cell
+pub fn cell(v: &Value) -> String {
+    return match v {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) => match n.as_f64() {
+            // Round floats so the columns stay readable
+            Some(f) if !n.is_i64() && !n.is_u64() => format!("{:.2}", f),
+            _ => n.to_string(),
+        },
+        _ => v.to_string(),
+    };
+}
+
+/// Flatten a JSON value into dotted keys and scalar values
+fn flatten(prefix: &str, v: &Value, out: &mut Vec<(String, String)>) {
+    let join = |k: &str| {
+        if prefix.is_empty() { k.to_string() } else { format!("{}.{}", prefix, k) }
+    };
+
+    match v {
+        Value::Object(m) => {
+            for (k, x) in m {
+                flatten(&join(k), x, out);
+            }
+        },
+        Value::Array(a) => {
+            for (i, x) in a.iter().enumerate() {
+                flatten(&join(&i.to_string()), x, out);
+            }
+        },
+        Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        Value::Null => out.push((prefix.to_string(), "".to_string())),
+        _ => out.push((prefix.to_string(), v.to_string())),
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", s.replace('"', "\"\""));
+    }
+
+    return s.to_string();
+}
+
+fn is_numeric(s: &str) -> bool {
+    let t = s.trim_end_matches('%');
+    return t == "-" || t.parse::<f64>().is_ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Results {
+        return vec![
+            ("http://a".to_string(), Some(json!({"x": {"y": 1}, "z": "q,r"}))),
+            ("http://b".to_string(), None),
+        ];
+    }
+
+    #[test]
+    fn test_table_render() {
+        let mut t = Table::new(&["NAME", "COUNT"]);
+        t.add_row(vec!["a".to_string(), "5".to_string()]);
+        t.add_row(vec!["bbb".to_string(), "100".to_string()]);
+        assert_eq!(t.render(), "NAME  COUNT\na         5\nbbb     100\n");
+    }
+
+    #[test]
+    fn test_render_json() {
+        let out = render(Format::Json, &results(), None).unwrap();
+        let doc: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(doc["http://a"]["x"]["y"], json!(1));
+        assert_eq!(doc["http://b"], Value::Null);
+        assert!(render(Format::Table, &results(), None).is_none());
+    }
+
+    #[test]
+    fn test_render_ndjson() {
+        let out = render(Format::Ndjson, &results(), None).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["server"], json!("http://a"));
+    }
+
+    #[test]
+    fn test_render_csv_flattened() {
+        let out = render(Format::Csv, &results(), None).unwrap();
+        assert_eq!(out, "server,key,value\nhttp://a,x.y,1\n\
+            http://a,z,\"q,r\"\nhttp://b,,\n");
+    }
+}
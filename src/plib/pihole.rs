@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
-use isahc::{prelude::*, Request, config::RedirectPolicy};
+use std::net::IpAddr;
+use isahc::{prelude::*, Body, Request, Response, config::RedirectPolicy, http};
 use serde_json::{self, Value, json};
 use log::{error, warn, debug};
 use super::config::PiServer;
@@ -32,6 +33,16 @@ impl Pihole {
         };
     }
 
+    /// Return a short name for the server, the host part of the url
+    pub fn name(&self) -> &str {
+        let no_scheme = match self.base_url.find("://") {
+            Some(i) => &self.base_url[i + 3..],
+            None => &self.base_url,
+        };
+
+        return no_scheme.split(['/', ':']).next().unwrap_or(no_scheme);
+    }
+
     /// Authenticate with the server, this should mostly be just an internal
     /// call, but is still accessible in general
     pub fn auth(&mut self) -> Option<Value> {
@@ -43,14 +54,36 @@ impl Pihole {
 
         if let Some(r) = &res {
             if let Some(sess) = r.get("session") {
-                // We didn't get an error here, so we can yank the session ID
-                self.sid = Some(sess["sid"].as_str().unwrap().to_string());
+                // The SID is null if the password was wrong, or if the
+                // server doesn't need one
+                self.sid = sess["sid"].as_str().map(|s| s.to_string());
             }
         }
 
         return res;
     }
 
+    /// End the current session, if there is one, so it doesn't take up one
+    /// of the server's session slots until it expires.  The session ID is
+    /// dropped either way
+    pub fn logout(&mut self) -> bool {
+        if self.sid.is_none() {
+            return true;
+        }
+
+        let mut url = self.build_url();
+        url.push_str("/auth");
+        debug!("Calling url: {}", &url);
+        // A session the server already dropped isn't worth a warning
+        let ret = match self.send_req(Request::delete(&url), vec![]) {
+            Some(r) => r.status().is_success(),
+            None => false,
+        };
+        self.sid = None;
+
+        return ret;
+    }
+
     /// Return the version of this server
     pub fn version(&self) -> Option<Value> {
         return self.run_get_cmd("info/version");
@@ -58,10 +91,7 @@ impl Pihole {
 
     /// Get the top domains
     pub fn top_items(&self, top_n: Option<usize>) -> Option<Value> {
-        let _top_n = match top_n {
-            None => 10,
-            Some(n) => n,
-        };
+        let _top_n = top_n.unwrap_or(10);
 
         let cmd = format!("stats/top_domains?count={}", _top_n);
         return self.run_get_cmd(&cmd);
@@ -69,10 +99,7 @@ impl Pihole {
 
     /// Get the top clients
     pub fn top_clients(&self, top_n: Option<usize>) -> Option<Value> {
-        let _top_n = match top_n {
-            None => 10,
-            Some(n) => n,
-        };
+        let _top_n = top_n.unwrap_or(10);
 
         let cmd = format!("stats/top_clients?count={}", _top_n);
         return self.run_get_cmd(&cmd);
@@ -93,15 +120,15 @@ impl Pihole {
         return self.run_get_cmd("stats/summary");
     }
 
+    /// Get the blocking state, and the timer if it's temporarily disabled
+    pub fn blocking(&self) -> Option<Value> {
+        return self.run_get_cmd("dns/blocking");
+    }
+
     /// Get the status from the summary
     pub fn status(&self) -> Option<String> {
-        let res = self.run_get_cmd("dns/blocking");
-        return match res {
-            None => None,
-            Some(s) => {
-                Some(s["blocking"].to_string())
-            },
-        };
+        let res = self.blocking();
+        return res.map(|s| s["blocking"].to_string());
     }
 
     /// Enable a server
@@ -130,6 +157,197 @@ impl Pihole {
         return self.run_get_cmd(&cmd);
     }
 
+    /// Get up to `length` queries logged between the `from` and `until`
+    /// timestamps.  The response also has the number of queries in that
+    /// range as `recordsFiltered`
+    pub fn queries(&self, from: i64, until: i64, length: usize) -> Option<Value> {
+        let cmd = format!("queries?from={}&until={}&length={}", from, until,
+            length);
+        return self.run_get_cmd(&cmd);
+    }
+
+    /// Get a config setting, or a whole section of settings, by its dotted
+    /// path (ex: "dns.upstreams").  With `detailed`, each setting also
+    /// includes its type, default and flags
+    pub fn get_config(&self, key: Option<&str>, detailed: bool) -> Option<Value> {
+        let mut cmd = "config".to_string();
+        if let Some(k) = key {
+            cmd.push('/');
+            cmd.push_str(&k.replace('.', "/"));
+        }
+        if detailed {
+            cmd.push_str("?detailed=true");
+        }
+
+        return self.run_get_cmd(&cmd);
+    }
+
+    /// Set a single config setting by its dotted path
+    pub fn set_config(&self, key: &str, value: Value) -> Option<Value> {
+        let mut body = value;
+        for part in key.rsplit('.') {
+            body = json!({ part: body });
+        }
+
+        return self.run_patch_cmd("config", json!({ "config": body }));
+    }
+
+    /// Get the current DHCP leases
+    pub fn dhcp_leases(&self) -> Option<Value> {
+        return self.run_get_cmd("dhcp/leases");
+    }
+
+    /// Delete the DHCP lease for an IP, returning true on success
+    pub fn delete_dhcp_lease(&self, ip: &IpAddr) -> bool {
+        return self.run_delete_cmd(&format!("dhcp/leases/{}", ip));
+    }
+
+    /// Get the devices seen on the network
+    pub fn network_devices(&self) -> Option<Value> {
+        return self.run_get_cmd("network/devices");
+    }
+
+    /// Delete a network device by its ID on this server
+    pub fn delete_network_device(&self, id: i64) -> bool {
+        return self.run_delete_cmd(&format!("network/devices/{}", id));
+    }
+
+    /// Get the gateway info for the server
+    pub fn network_gateway(&self) -> Option<Value> {
+        return self.run_get_cmd("network/gateway");
+    }
+
+    /// Get the network interfaces on the server
+    pub fn network_interfaces(&self) -> Option<Value> {
+        return self.run_get_cmd("network/interfaces");
+    }
+
+    /// Get the system info (uptime, memory, load) for the host
+    pub fn info_system(&self) -> Option<Value> {
+        return self.run_get_cmd("info/system");
+    }
+
+    /// Get the host info (uname, model, dmi)
+    pub fn info_host(&self) -> Option<Value> {
+        return self.run_get_cmd("info/host");
+    }
+
+    /// Get the FTL process info
+    pub fn info_ftl(&self) -> Option<Value> {
+        return self.run_get_cmd("info/ftl");
+    }
+
+    /// Get the long term query database info
+    pub fn info_database(&self) -> Option<Value> {
+        return self.run_get_cmd("info/database");
+    }
+
+    /// Get the sensor readings, including the CPU temperature
+    pub fn info_sensors(&self) -> Option<Value> {
+        return self.run_get_cmd("info/sensors");
+    }
+
+    /// Get the DNS and DHCP metrics
+    pub fn info_metrics(&self) -> Option<Value> {
+        return self.run_get_cmd("info/metrics");
+    }
+
+    /// Get the FTL diagnosis messages
+    pub fn messages(&self) -> Option<Value> {
+        return self.run_get_cmd("info/messages");
+    }
+
+    /// Delete (acknowledge) a diagnosis message by its ID on this server
+    pub fn delete_message(&self, id: i64) -> bool {
+        return self.run_delete_cmd(&format!("info/messages/{}", id));
+    }
+
+    /// Get the lines from one of the logs (dnsmasq, ftl or webserver).
+    /// With `next_id`, only the lines newer than that cursor are returned
+    pub fn logs(&self, log: &str, next_id: Option<i64>) -> Option<Value> {
+        let cmd = match next_id {
+            Some(id) => format!("logs/{}?nextID={}", log, id),
+            None => format!("logs/{}", log),
+        };
+
+        return self.run_get_cmd(&cmd);
+    }
+
+    /// Export a teleporter archive (zip) with this server's configuration
+    pub fn teleporter_export(&self) -> Option<Vec<u8>> {
+        let mut url = self.build_url();
+        url.push_str("/teleporter");
+        debug!("Calling url: {}", &url);
+
+        let mut resp = self.send_req(Request::get(&url), vec![])?;
+        if !resp.status().is_success() {
+            error!("Failed to export teleporter archive from {}: HTTP {}",
+                self.base_url, resp.status());
+            return None;
+        }
+
+        return match resp.bytes() {
+            Ok(b) => Some(b),
+            Err(e) => {
+                error!("Failed to read teleporter archive from {}: {}",
+                    url, e);
+                None
+            },
+        };
+    }
+
+    /// Import a teleporter archive.  The `import` value, if given, selects
+    /// which categories are restored, otherwise everything in the archive
+    /// is imported
+    pub fn teleporter_import(
+        &self,
+        zip: &[u8],
+        import: Option<Value>,
+    ) -> Option<Value> {
+        let mut url = self.build_url();
+        url.push_str("/teleporter");
+        debug!("Calling url: {}", &url);
+
+        let boundary = format!("----pi-ctl-{}",
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0));
+        let mut body: Vec<u8> = vec![];
+        body.extend_from_slice(format!(
+            "--{}\r\n\
+            Content-Disposition: form-data; name=\"file\"; \
+            filename=\"teleporter.zip\"\r\n\
+            Content-Type: application/zip\r\n\r\n", boundary).as_bytes());
+        body.extend_from_slice(zip);
+        body.extend_from_slice(b"\r\n");
+        if let Some(imp) = import {
+            body.extend_from_slice(format!(
+                "--{}\r\n\
+                Content-Disposition: form-data; name=\"import\"\r\n\r\n\
+                {}\r\n", boundary, imp).as_bytes());
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        let req = Request::post(&url).header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        );
+        let text = match self.send_req(req, body)?.text() {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Failed to get response body from {}: {}", url, e);
+                return None;
+            },
+        };
+        debug!("Received response from server: {}", &text);
+
+        return match serde_json::from_str::<Value>(&text) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("Failed to parse JSON body {}\n{}", e, &text);
+                None
+            },
+        };
+    }
+
     /*
      * Private methods for internal use
      */
@@ -140,7 +358,7 @@ impl Pihole {
         url.push_str(&format!("/{}", cmd));
         debug!("Calling url: {}", &url);
 
-        return self.call_url(&url, None);
+        return self.call_url("GET", &url, None);
     }
 
     /// This is a high level function to run a POST command with no frills
@@ -150,11 +368,45 @@ impl Pihole {
 
 
         debug!("Calling url: {}", &url);
-        return self.call_url(&url, Some(&data.to_string()));
+        return self.call_url("POST", &url, Some(&data.to_string()));
+    }
+
+    /// This is a high level function to run a PATCH command with no frills
+    fn run_patch_cmd(&self, cmd: &str, data: Value) -> Option<Value> {
+        let mut url = self.build_url();
+        url.push_str(&format!("/{}", cmd));
+
+        debug!("Calling url: {}", &url);
+        return self.call_url("PATCH", &url, Some(&data.to_string()));
+    }
+
+    /// Run a DELETE command.  These have no response body on success, so
+    /// this just returns whether the server accepted it
+    fn run_delete_cmd(&self, cmd: &str) -> bool {
+        let mut url = self.build_url();
+        url.push_str(&format!("/{}", cmd));
+        debug!("Calling url: {}", &url);
+
+        let mut resp = match self.send_req(Request::delete(&url), vec![]) {
+            Some(r) => r,
+            None => return false,
+        };
+        if !resp.status().is_success() {
+            warn!("DELETE {} failed with HTTP {}: {}", url, resp.status(),
+                resp.text().unwrap_or_default());
+            return false;
+        }
+
+        return true;
     }
 
-    fn call_url(&self, url: &str, data: Option<&str>) -> Option<Value> {
-        let json_body = match self.get_url_resp_body(&url, data) {
+    fn call_url(
+        &self,
+        method: &str,
+        url: &str,
+        data: Option<&str>,
+    ) -> Option<Value> {
+        let json_body = match self.get_url_resp_body(method, url, data) {
             Some(b) => b,
             _ => { return None; }
         };
@@ -172,33 +424,23 @@ impl Pihole {
         }
     }
 
-    fn get_url_resp_body(&self, url: &str, body: Option<&str>) -> Option<String> {
-        // If we have a body, it's a POST request of type application/json
+    fn get_url_resp_body(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&str>,
+    ) -> Option<String> {
+        let req = Request::builder().method(method).uri(url);
+        // If we have a body, it's a request of type application/json
         let resp = match body {
-            Some(b) => {
-                let mut req = Request::post(url)
-                    .redirect_policy(RedirectPolicy::Follow)
-                    .header("Content-Type", "application/json");
-                if let Some(sid) = &self.sid {
-                    req = req.header("X-FTL-SID", sid);
-                }
-                req.body(b).unwrap().send()
-            },
-            None => {
-                Request::get(url)
-                    .redirect_policy(RedirectPolicy::Follow)
-                    .header("X-FTL-SID", self.sid.as_ref().unwrap())
-                    .body(()).unwrap()
-                    .send()
-            }
+            Some(b) => self.send_req(
+                req.header("Content-Type", "application/json"),
+                b.as_bytes().to_vec(),
+            ),
+            None => self.send_req(req, vec![]),
         };
 
-        if let Err(e) = resp {
-            error!("Failed to send request to {}: {}", url, e);
-            return None;
-        }
-
-        let ret = match resp.unwrap().text() {
+        let ret = match resp?.text() {
             Ok(t) => t,
             Err(e) => {
                 error!("Failed to get response body from {}: {}", url, e);
@@ -209,6 +451,33 @@ impl Pihole {
         return Some(ret);
     }
 
+    /// Send a request built by the caller, adding the session ID and the
+    /// redirect policy.  This is the lowest level call that everything else
+    /// goes through
+    fn send_req(
+        &self,
+        builder: http::request::Builder,
+        body: Vec<u8>,
+    ) -> Option<Response<Body>> {
+        let mut req = builder.redirect_policy(RedirectPolicy::Follow);
+        if let Some(sid) = &self.sid {
+            req = req.header("X-FTL-SID", sid);
+        }
+
+        let url = match req.uri_ref() {
+            Some(u) => u.to_string(),
+            None => "".to_string(),
+        };
+
+        return match req.body(body).unwrap().send() {
+            Ok(r) => Some(r),
+            Err(e) => {
+                error!("Failed to send request to {}: {}", url, e);
+                None
+            },
+        };
+    }
+
     fn build_url(&self) -> String {
         let mut ret = "".to_string();
 
@@ -244,6 +513,23 @@ mod tests {
         assert!(pihole.sid.is_none());
     }
 
+    #[test]
+    fn test_name() {
+        let pihole = Pihole::new("https://pihole.local:8443/", "password");
+        assert_eq!(pihole.name(), "pihole.local");
+        let pihole = Pihole::new("10.0.0.2", "password");
+        assert_eq!(pihole.name(), "10.0.0.2");
+    }
+
+    #[test]
+    fn test_logout() {
+        let mut pihole = Pihole::new("http://127.0.0.1:1", "password");
+        assert!(pihole.logout());
+        pihole.sid = Some("abc".to_string());
+        assert!(!pihole.logout());
+        assert!(pihole.sid.is_none());
+    }
+
     #[test]
     fn test_build_url() {
         let pihole = Pihole::new("http://localhost", "password");
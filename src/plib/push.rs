@@ -0,0 +1,276 @@
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+use isahc::{prelude::*, Request};
+use serde_json::Value;
+use super::backup::server_slug;
+
+/// The names accepted for `push-metrics --format`
+pub const PROTOCOLS: &[&str] = &["influx", "graphite"];
+
+/// Keep each UDP datagram under a typical MTU
+const MAX_DATAGRAM: usize = 1400;
+
+/// The wire formats that the metrics can be pushed in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Influx,
+    Graphite,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "influx" => Ok(Protocol::Influx),
+            "graphite" => Ok(Protocol::Graphite),
+            _ => Err(format!("Unknown protocol: {}", s)),
+        };
+    }
+}
+
+/// Where the metrics are sent, from a url like "udp://host:8089",
+/// "tcp://host:2003" or "http://host:8086/write?db=pihole"
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    Udp(String),
+    Tcp(String),
+    Http(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            return Ok(Endpoint::Udp(addr.trim_end_matches('/').to_string()));
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            return Ok(Endpoint::Tcp(addr.trim_end_matches('/').to_string()));
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Endpoint::Http(s.to_string()));
+        }
+
+        return Err(format!("The endpoint must be a udp://, tcp://, http:// \
+            or https:// url: {}", s));
+    }
+}
+
+impl Endpoint {
+    /// Send the lines to the endpoint.  The headers are only used for
+    /// HTTP, ex: for an InfluxDB token
+    pub fn send(&self, lines: &[String], headers: &[(String, String)]) -> Result<(), String> {
+        return match self {
+            Endpoint::Udp(addr) => {
+                let sock = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+                for dgram in datagrams(lines, MAX_DATAGRAM) {
+                    sock.send_to(dgram.as_bytes(), addr.as_str())
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            },
+            Endpoint::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr.as_str())
+                    .map_err(|e| e.to_string())?;
+                stream.set_write_timeout(Some(Duration::from_secs(10)))
+                    .map_err(|e| e.to_string())?;
+                stream.write_all(join_lines(lines).as_bytes())
+                    .map_err(|e| e.to_string())
+            },
+            Endpoint::Http(url) => {
+                let mut req = Request::post(url)
+                    .header("Content-Type", "text/plain; charset=utf-8");
+                for (k, v) in headers {
+                    req = req.header(k.as_str(), v.as_str());
+                }
+                let mut resp = req.body(join_lines(lines))
+                    .map_err(|e| e.to_string())?
+                    .send()
+                    .map_err(|e| e.to_string())?;
+                if !resp.status().is_success() {
+                    return Err(format!("HTTP {}: {}", resp.status(),
+                        resp.text().unwrap_or_default()));
+                }
+                Ok(())
+            },
+        };
+    }
+}
+
+/// The stats for one server at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct Sample {
+    pub server: String,
+    pub timestamp: i64,
+    pub summary: Option<Value>,
+    pub query_types: Option<Value>,
+}
+
+impl Sample {
+    /// The fields for each measurement, "summary" and "query_types".
+    /// Missing values are left out
+    fn measurements(&self) -> Vec<(&'static str, Vec<(String, Value)>)> {
+        let mut ret = vec![];
+        if let Some(v) = &self.summary {
+            let mut fields = vec![];
+            for (name, val) in [
+                ("total", &v["queries"]["total"]),
+                ("blocked", &v["queries"]["blocked"]),
+                ("forwarded", &v["queries"]["forwarded"]),
+                ("cached", &v["queries"]["cached"]),
+                ("percent_blocked", &v["queries"]["percent_blocked"]),
+                ("unique_domains", &v["queries"]["unique_domains"]),
+                ("clients_active", &v["clients"]["active"]),
+                ("gravity_domains", &v["gravity"]["domains_being_blocked"]),
+            ] {
+                if val.is_number() {
+                    fields.push((name.to_string(), val.clone()));
+                }
+            }
+            ret.push(("summary", fields));
+        }
+        if let Some(types) = self.query_types.as_ref()
+            .and_then(|v| v["types"].as_object())
+        {
+            let fields = types.iter()
+                .filter(|(_, n)| n.is_number())
+                .map(|(t, n)| (t.clone(), n.clone()))
+                .collect();
+            ret.push(("query_types", fields));
+        }
+
+        return ret.into_iter().filter(|(_, f)| !f.is_empty()).collect();
+    }
+}
+
+/// Format the samples as lines of the chosen protocol.  InfluxDB gets one
+/// line per measurement, tagged by server, and Graphite gets one line per
+/// value under `prefix.<server>.<measurement>`
+pub fn format(proto: Protocol, samples: &[Sample], prefix: &str) -> Vec<String> {
+    let mut ret = vec![];
+    for s in samples {
+        for (meas, fields) in s.measurements() {
+            match proto {
+                Protocol::Influx => {
+                    let fields: Vec<String> = fields.iter()
+                        .map(|(k, v)| format!("{}={}", influx_escape(k),
+                            influx_value(v)))
+                        .collect();
+                    ret.push(format!("{}_{},server={} {} {}",
+                        prefix, meas, influx_escape(&s.server),
+                        fields.join(","), s.timestamp * 1_000_000_000));
+                },
+                Protocol::Graphite => {
+                    let server = server_slug(&s.server).replace('.', "_");
+                    for (k, v) in fields {
+                        ret.push(format!("{}.{}.{}.{} {} {}", prefix, server,
+                            meas, graphite_escape(&k), v, s.timestamp));
+                    }
+                },
+            }
+        }
+    }
+
+    return ret;
+}
+
+fn join_lines(lines: &[String]) -> String {
+    let mut ret = lines.join("\n");
+    ret.push('\n');
+    return ret;
+}
+
+/// Pack whole lines into datagrams of up to `max` bytes.  A line longer
+/// than that gets a datagram of its own
+fn datagrams(lines: &[String], max: usize) -> Vec<String> {
+    let mut ret: Vec<String> = vec![];
+    let mut cur = String::new();
+    for l in lines {
+        if !cur.is_empty() && cur.len() + l.len() + 1 > max {
+            ret.push(std::mem::take(&mut cur));
+        }
+        cur.push_str(l);
+        cur.push('\n');
+    }
+    if !cur.is_empty() {
+        ret.push(cur);
+    }
+
+    return ret;
+}
+
+/// Integers need an "i" suffix in the line protocol, or they are floats
+fn influx_value(v: &Value) -> String {
+    if v.is_i64() || v.is_u64() {
+        return format!("{}i", v);
+    }
+
+    return v.to_string();
+}
+
+/// Escape a tag value or field key for the line protocol
+fn influx_escape(s: &str) -> String {
+    return s.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ");
+}
+
+fn graphite_escape(s: &str) -> String {
+    return s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn samples() -> Vec<Sample> {
+        return vec![Sample {
+            server: "http://pi.lan:8080".to_string(),
+            timestamp: 1700000000,
+            summary: Some(json!({"queries": {"total": 100, "blocked": 12,
+                "percent_blocked": 12.5}})),
+            query_types: Some(json!({"types": {"A": 60, "AAAA": 40}})),
+        }];
+    }
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!("udp://influx:8089".parse::<Endpoint>(),
+            Ok(Endpoint::Udp("influx:8089".to_string())));
+        assert_eq!("tcp://graphite:2003/".parse::<Endpoint>(),
+            Ok(Endpoint::Tcp("graphite:2003".to_string())));
+        assert!(matches!("https://x/write?db=p".parse::<Endpoint>(),
+            Ok(Endpoint::Http(_))));
+        assert!("graphite:2003".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn test_format_influx() {
+        let lines = format(Protocol::Influx, &samples(), "pihole");
+        assert_eq!(lines, vec![
+            "pihole_summary,server=http://pi.lan:8080 total=100i,blocked=12i,\
+                percent_blocked=12.5 1700000000000000000",
+            "pihole_query_types,server=http://pi.lan:8080 A=60i,AAAA=40i \
+                1700000000000000000",
+        ]);
+    }
+
+    #[test]
+    fn test_format_graphite() {
+        let lines = format(Protocol::Graphite, &samples(), "pihole");
+        assert_eq!(lines[0], "pihole.pi_lan_8080.summary.total 100 1700000000");
+        assert_eq!(lines[2],
+            "pihole.pi_lan_8080.summary.percent_blocked 12.5 1700000000");
+        assert_eq!(lines[4], "pihole.pi_lan_8080.query_types.AAAA 40 1700000000");
+    }
+
+    #[test]
+    fn test_datagrams() {
+        let lines: Vec<String> = vec!["aaaa".into(), "bbbb".into(), "cc".into()];
+        assert_eq!(datagrams(&lines, 10), vec!["aaaa\nbbbb\n", "cc\n"]);
+    }
+}
@@ -0,0 +1,282 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeSet;
+use serde_json::Value;
+use super::output::{cell, Results, Table};
+
+/// One row per server with the main counters from `stats/summary`
+pub fn summary(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "TOTAL", "BLOCKED", "% BLOCKED",
+        "UNIQUE DOMAINS", "FORWARDED", "CACHED", "CLIENTS", "GRAVITY"]);
+    for (server, val) in results {
+        let v = match val {
+            Some(v) => v,
+            None => continue,
+        };
+        let q = &v["queries"];
+        t.add_row(vec![
+            server.clone(),
+            cell(&q["total"]),
+            cell(&q["blocked"]),
+            format!("{}%", cell(&q["percent_blocked"])),
+            cell(&q["unique_domains"]),
+            cell(&q["forwarded"]),
+            cell(&q["cached"]),
+            cell(&v["clients"]["active"]),
+            cell(&v["gravity"]["domains_being_blocked"]),
+        ]);
+    }
+
+    return t;
+}
+
+/// The ranked domains from `stats/top_domains` for each server
+pub fn top_domains(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "RANK", "DOMAIN", "COUNT"]);
+    for (server, val) in results {
+        for (i, d) in list(val, "domains").iter().enumerate() {
+            t.add_row(vec![
+                server.clone(),
+                (i + 1).to_string(),
+                cell(&d["domain"]),
+                cell(&d["count"]),
+            ]);
+        }
+    }
+
+    return t;
+}
+
+/// The ranked clients from `stats/top_clients` for each server
+pub fn top_clients(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "RANK", "NAME", "IP", "COUNT"]);
+    for (server, val) in results {
+        for (i, c) in list(val, "clients").iter().enumerate() {
+            t.add_row(vec![
+                server.clone(),
+                (i + 1).to_string(),
+                name_or_dash(&c["name"]),
+                cell(&c["ip"]),
+                cell(&c["count"]),
+            ]);
+        }
+    }
+
+    return t;
+}
+
+/// The forward destinations from `stats/upstreams` for each server
+pub fn upstreams(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "UPSTREAM", "NAME", "COUNT",
+        "RESPONSE MS"]);
+    for (server, val) in results {
+        for u in list(val, "upstreams") {
+            let addr = match u["port"].as_i64() {
+                Some(p) if p > 0 => format!("{}#{}", cell(&u["ip"]), p),
+                _ => cell(&u["ip"]),
+            };
+            // The response time is reported in seconds
+            let resp = match u["statistics"]["response"].as_f64() {
+                Some(r) => format!("{:.1}", r * 1000.0),
+                None => "-".to_string(),
+            };
+            t.add_row(vec![
+                server.clone(),
+                addr,
+                name_or_dash(&u["name"]),
+                cell(&u["count"]),
+                resp,
+            ]);
+        }
+    }
+
+    return t;
+}
+
+/// One row per query type with a column for each server
+pub fn query_types(results: &Results) -> Table {
+    let mut headers = vec!["TYPE"];
+    headers.extend(results.iter().map(|(s, _)| s.as_str()));
+    let mut t = Table::new(&headers);
+
+    let mut types: BTreeSet<String> = BTreeSet::new();
+    for (_, val) in results {
+        if let Some(m) = val.as_ref().and_then(|v| v["types"].as_object()) {
+            types.extend(m.keys().cloned());
+        }
+    }
+
+    for qtype in types {
+        let mut row = vec![qtype.clone()];
+        for (_, val) in results {
+            row.push(match val {
+                Some(v) => cell(&v["types"][&qtype]),
+                None => "-".to_string(),
+            });
+        }
+        t.add_row(row);
+    }
+
+    return t;
+}
+
+/// The blocking state from `dns/blocking` for each server
+pub fn status(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "BLOCKING", "TIMER"]);
+    for (server, val) in results {
+        let v = match val {
+            Some(v) => v,
+            None => continue,
+        };
+        t.add_row(vec![server.clone(), cell(&v["blocking"]), cell(&v["timer"])]);
+    }
+
+    return t;
+}
+
+/// The recently blocked domains for each server
+pub fn recent_blocked(results: &Results) -> Table {
+    let mut t = Table::new(&["SERVER", "DOMAIN"]);
+    for (server, val) in results {
+        for d in list(val, "blocked") {
+            t.add_row(vec![server.clone(), cell(&d)]);
+        }
+    }
+
+    return t;
+}
+
+/// The summary table for each server, with a row for the fleet totals
+pub fn fleet_summary(results: &Results, agg: &Value) -> Table {
+    let fleet: Results = vec![("TOTAL".to_string(), Some(agg.clone()))];
+    let mut t = summary(results);
+    t.rows.extend(summary(&fleet).rows);
+
+    return t;
+}
+
+/// The re-ranked fleet items from `aggregate::top_items()` with the count
+/// from each server.  `name` adds a column for the client names
+pub fn fleet_top(
+    agg: &Value,
+    list: &str,
+    key: &str,
+    servers: &[String],
+    name: bool,
+) -> Table {
+    let key_col = key.to_uppercase();
+    let mut headers = vec!["RANK", &key_col];
+    if name {
+        headers.push("NAME");
+    }
+    headers.push("TOTAL");
+    headers.extend(servers.iter().map(|s| s.as_str()));
+    let mut t = Table::new(&headers);
+
+    for (i, item) in agg[list].as_array().cloned().unwrap_or_default()
+        .iter().enumerate()
+    {
+        let mut row = vec![(i + 1).to_string(), cell(&item[key])];
+        if name {
+            row.push(name_or_dash(&item["name"]));
+        }
+        row.push(cell(&item["count"]));
+        row.extend(servers.iter().map(|s| cell(&item["servers"][s])));
+        t.add_row(row);
+    }
+
+    return t;
+}
+
+/// The merged upstreams from `aggregate::upstreams()` with the count from
+/// each server
+pub fn fleet_upstreams(agg: &Value, servers: &[String]) -> Table {
+    let mut headers = vec!["UPSTREAM", "NAME", "TOTAL", "RESPONSE MS"];
+    headers.extend(servers.iter().map(|s| s.as_str()));
+    let mut t = Table::new(&headers);
+
+    for u in agg["upstreams"].as_array().cloned().unwrap_or_default() {
+        let mut row = vec![
+            format!("{}#{}", cell(&u["ip"]), cell(&u["port"])),
+            name_or_dash(&u["name"]),
+            cell(&u["count"]),
+            match u["statistics"]["response"].as_f64() {
+                Some(r) => format!("{:.1}", r * 1000.0),
+                None => "-".to_string(),
+            },
+        ];
+        row.extend(servers.iter().map(|s| cell(&u["servers"][s])));
+        t.add_row(row);
+    }
+
+    return t;
+}
+
+/// The fleet query types from `aggregate::query_types()`, with the total,
+/// the percentage and a column for each server
+pub fn fleet_query_types(results: &Results, agg: &Value) -> Table {
+    let mut headers = vec!["TYPE", "TOTAL", "%"];
+    headers.extend(results.iter().map(|(s, _)| s.as_str()));
+    let mut t = Table::new(&headers);
+
+    for (qtype, total) in agg["types"].as_object().cloned().unwrap_or_default() {
+        let mut row = vec![
+            qtype.clone(),
+            cell(&total),
+            format!("{:.1}", agg["percent"][&qtype].as_f64().unwrap_or(0.0)),
+        ];
+        for (_, val) in results {
+            row.push(match val {
+                Some(v) => cell(&v["types"][&qtype]),
+                None => "-".to_string(),
+            });
+        }
+        t.add_row(row);
+    }
+
+    return t;
+}
+
+fn list(val: &Option<Value>, key: &str) -> Vec<Value> {
+    return val.as_ref()
+        .and_then(|v| v[key].as_array().cloned())
+        .unwrap_or_default();
+}
+
+fn name_or_dash(v: &Value) -> String {
+    return match v.as_str() {
+        Some(s) if !s.is_empty() => s.to_string(),
+        _ => "-".to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_query_types_pivot() {
+        let results: Results = vec![
+            ("a".to_string(), Some(json!({"types": {"A": 10, "AAAA": 2}}))),
+            ("b".to_string(), Some(json!({"types": {"A": 7, "MX": 1}}))),
+            ("c".to_string(), None),
+        ];
+        let t = query_types(&results);
+        assert_eq!(t.headers, vec!["TYPE", "a", "b", "c"]);
+        assert_eq!(t.rows[0], vec!["A", "10", "7", "-"]);
+        assert_eq!(t.rows[2], vec!["MX", "-", "1", "-"]);
+    }
+
+    #[test]
+    fn test_upstreams() {
+        let results: Results = vec![("a".to_string(), Some(json!({
+            "upstreams": [{"ip": "1.1.1.1", "name": "one.one.one.one",
+                "port": 53, "count": 9,
+                "statistics": {"response": 0.0125, "variance": 0.0}}],
+        })))];
+        let t = upstreams(&results);
+        assert_eq!(t.rows[0], vec!["a", "1.1.1.1#53", "one.one.one.one",
+            "9", "12.5"]);
+    }
+}
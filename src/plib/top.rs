@@ -0,0 +1,387 @@
+#![allow(dead_code)]
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use serde_json::Value;
+use super::output::{cell, Table};
+use super::pihole::Pihole;
+
+/// The settings for the `top` dashboard
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub interval: Duration,
+    pub disable_secs: usize,
+    pub recent: usize,
+    pub clients: usize,
+}
+
+/// Everything fetched from one server in a refresh.  A failed call, or an
+/// error response like an expired session, leaves its part empty
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub blocking: Option<Value>,
+    pub summary: Option<Value>,
+    pub recent: Option<Value>,
+    pub clients: Option<Value>,
+}
+
+impl Snapshot {
+    pub fn fetch(s: &Pihole, opts: &Options) -> Self {
+        return Self {
+            blocking: ok(s.blocking()),
+            summary: ok(s.summary()),
+            recent: ok(s.recent_blocked(opts.recent)),
+            clients: ok(s.top_clients(Some(opts.clients))),
+        };
+    }
+
+    /// The server is considered unreachable if it didn't return its status
+    pub fn reachable(&self) -> bool {
+        return self.blocking.as_ref()
+            .is_some_and(|b| b.get("error").is_none());
+    }
+
+    pub fn total_queries(&self) -> Option<i64> {
+        return self.summary.as_ref()
+            .and_then(|v| v["queries"]["total"].as_i64());
+    }
+}
+
+/// Drop error responses, ex: a 401 for an expired session, like failed calls
+fn ok(v: Option<Value>) -> Option<Value> {
+    return v.filter(|x| x.get("error").is_none());
+}
+
+/// The queries per second between two refreshes.  The summary counts cover
+/// a rolling 24 hours, so the total can drop as old queries age out, in
+/// which case there is no rate to show
+pub fn rate(prev: Option<i64>, cur: Option<i64>, elapsed: f64) -> Option<f64> {
+    return match (prev, cur) {
+        (Some(p), Some(c)) if c >= p && elapsed > 0.0 =>
+            Some((c - p) as f64 / elapsed),
+        _ => None,
+    };
+}
+
+/// Format the seconds left on a disable timer as m:ss
+pub fn fmt_timer(secs: f64) -> String {
+    let secs = secs.max(0.0) as i64;
+    return format!("{}:{:02}", secs / 60, secs % 60);
+}
+
+/// The server table, with the blocking state, the disable timer counted
+/// down by `since` seconds since the refresh and the query rate
+pub fn servers_table(
+    names: &[String],
+    snaps: &[Snapshot],
+    rates: &[Option<f64>],
+    since: f64,
+) -> Table {
+    let mut t = Table::new(&["SERVER", "BLOCKING", "TIMER", "Q/S", "TOTAL",
+        "BLOCKED", "% BLOCKED", "CLIENTS"]);
+    for (i, snap) in snaps.iter().enumerate() {
+        if !snap.reachable() {
+            t.add_row(vec![names[i].clone(), "unreachable".to_string()]);
+            continue;
+        }
+        let b = snap.blocking.as_ref().unwrap();
+        let timer = match b["timer"].as_f64() {
+            Some(secs) => fmt_timer(secs - since),
+            None => "-".to_string(),
+        };
+        let q = snap.summary.as_ref().map(|v| v["queries"].clone())
+            .unwrap_or(Value::Null);
+        t.add_row(vec![
+            names[i].clone(),
+            cell(&b["blocking"]),
+            timer,
+            match rates.get(i).cloned().flatten() {
+                Some(r) => format!("{:.1}", r),
+                None => "-".to_string(),
+            },
+            cell(&q["total"]),
+            cell(&q["blocked"]),
+            match q["percent_blocked"].as_f64() {
+                Some(p) => format!("{:.1}%", p),
+                None => "-".to_string(),
+            },
+            snap.summary.as_ref()
+                .map(|v| cell(&v["clients"]["active"]))
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    return t;
+}
+
+/// The recently blocked domains for each server
+pub fn recent_table(names: &[String], snaps: &[Snapshot]) -> Table {
+    let mut t = Table::new(&["SERVER", "RECENTLY BLOCKED"]);
+    for (i, snap) in snaps.iter().enumerate() {
+        let blocked = snap.recent.as_ref()
+            .and_then(|v| v["blocked"].as_array().cloned())
+            .unwrap_or_default();
+        for d in blocked {
+            t.add_row(vec![names[i].clone(), cell(&d)]);
+        }
+    }
+
+    return t;
+}
+
+/// The top clients for each server
+pub fn clients_table(names: &[String], snaps: &[Snapshot]) -> Table {
+    let mut t = Table::new(&["SERVER", "CLIENT", "IP", "COUNT"]);
+    for (i, snap) in snaps.iter().enumerate() {
+        let clients = snap.clients.as_ref()
+            .and_then(|v| v["clients"].as_array().cloned())
+            .unwrap_or_default();
+        for c in clients {
+            let name = match c["name"].as_str() {
+                Some(n) if !n.is_empty() => n.to_string(),
+                _ => "-".to_string(),
+            };
+            t.add_row(vec![names[i].clone(), name, cell(&c["ip"]),
+                cell(&c["count"])]);
+        }
+    }
+
+    return t;
+}
+
+/// Build the lines of the dashboard, cut to fit the terminal
+pub fn screen(
+    header: &str,
+    message: &str,
+    tables: &[Table],
+    width: usize,
+    height: usize,
+) -> Vec<String> {
+    let mut lines = vec![header.to_string(), message.to_string()];
+    for t in tables {
+        lines.push(String::new());
+        lines.extend(t.render().lines().map(|l| l.to_string()));
+    }
+
+    lines.truncate(height);
+    return lines.into_iter()
+        .map(|l| l.chars().take(width).collect())
+        .collect();
+}
+
+/// The short server names, or the urls without the scheme if the short
+/// names aren't unique (ex: several servers on one host)
+fn display_names(servers: &[Pihole]) -> Vec<String> {
+    let mut names: Vec<&str> = servers.iter().map(|s| s.name()).collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.len() == servers.len() {
+        return servers.iter().map(|s| s.name().to_string()).collect();
+    }
+
+    return servers.iter()
+        .map(|s| s.base_url.split("://").last().unwrap_or("").to_string())
+        .collect();
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and restores it
+/// when dropped, even on a panic
+struct Term;
+
+impl Term {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        return Ok(Self);
+    }
+}
+
+impl Drop for Term {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn fetch_all(servers: &[Pihole], opts: &Options) -> Vec<Snapshot> {
+    return thread::scope(|scope| {
+        let handles: Vec<_> = servers.iter()
+            .map(|s| scope.spawn(move || Snapshot::fetch(s, opts)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap_or_default()).collect()
+    });
+}
+
+fn draw(lines: &[String]) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    for (i, l) in lines.iter().enumerate() {
+        queue!(out, MoveTo(0, i as u16), Print(l),
+            Clear(ClearType::UntilNewLine))?;
+    }
+    queue!(out, Clear(ClearType::FromCursorDown))?;
+    return out.flush();
+}
+
+/// Run the dashboard until the user quits.  `d` disables blocking on all
+/// the servers for `disable_secs`, `e` enables it, `r` refreshes right away
+/// and `q` quits
+pub fn run(servers: &[Pihole], opts: &Options) -> io::Result<()> {
+    // Log output would scribble over the dashboard
+    let log_level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+
+    let ret = match Term::enter() {
+        Ok(_term) => dashboard(servers, opts),
+        Err(e) => Err(e),
+    };
+
+    log::set_max_level(log_level);
+    return ret;
+}
+
+fn dashboard(servers: &[Pihole], opts: &Options) -> io::Result<()> {
+    let names = display_names(servers);
+    let mut snaps = fetch_all(servers, opts);
+    let mut fetched = Instant::now();
+    let mut rates: Vec<Option<f64>> = vec![None; servers.len()];
+    let mut message = String::new();
+
+    loop {
+        let next = fetched + opts.interval;
+        // Redraw every second so the disable timers count down
+        while Instant::now() < next {
+            let since = fetched.elapsed().as_secs_f64();
+            let (w, h) = terminal::size()?;
+            let header = format!("pi-ctl top - {} - every {}s - \
+                d: disable {}s  e: enable  r: refresh  q: quit",
+                chrono::Local::now().format("%H:%M:%S"),
+                opts.interval.as_secs(), opts.disable_secs);
+            let tables = [
+                servers_table(&names, &snaps, &rates, since),
+                recent_table(&names, &snaps),
+                clients_table(&names, &snaps),
+            ];
+            draw(&screen(&header, &message, &tables, w as usize, h as usize))?;
+
+            let wait = next.saturating_duration_since(Instant::now())
+                .min(Duration::from_secs(1));
+            if !event::poll(wait)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(k) if k.kind == KeyEventKind::Press => k,
+                _ => continue,
+            };
+            let toggled = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c')
+                    if key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    return Ok(()),
+                KeyCode::Char('d') => Some(("Disabled", servers.iter()
+                    .filter(|s| ok(s.disable(opts.disable_secs)).is_some())
+                    .count())),
+                KeyCode::Char('e') => Some(("Enabled", servers.iter()
+                    .filter(|s| ok(s.enable()).is_some())
+                    .count())),
+                KeyCode::Char('r') => None,
+                _ => continue,
+            };
+            if let Some((what, n)) = toggled {
+                message = format!("{} blocking on {} of {} servers at {}",
+                    what, n, servers.len(),
+                    chrono::Local::now().format("%H:%M:%S"));
+            }
+            break;
+        }
+
+        let prev = snaps;
+        let elapsed = fetched.elapsed().as_secs_f64();
+        snaps = fetch_all(servers, opts);
+        fetched = Instant::now();
+        rates = prev.iter().zip(&snaps)
+            .map(|(p, c)| rate(p.total_queries(), c.total_queries(), elapsed))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(rate(Some(100), Some(130), 10.0), Some(3.0));
+        assert_eq!(rate(Some(100), Some(90), 10.0), None);
+        assert_eq!(rate(None, Some(90), 10.0), None);
+    }
+
+    #[test]
+    fn test_fmt_timer() {
+        assert_eq!(fmt_timer(299.6), "4:59");
+        assert_eq!(fmt_timer(-3.0), "0:00");
+    }
+
+    #[test]
+    fn test_servers_table() {
+        let names = vec!["a".to_string(), "b".to_string()];
+        let snaps = vec![
+            Snapshot {
+                blocking: Some(json!({"blocking": "disabled", "timer": 60.0})),
+                summary: Some(json!({"queries": {"total": 10, "blocked": 1,
+                    "percent_blocked": 10.0}, "clients": {"active": 2}})),
+                ..Default::default()
+            },
+            Snapshot::default(),
+        ];
+        let t = servers_table(&names, &snaps, &[Some(1.5), None], 5.0);
+        assert_eq!(t.rows[0], vec!["a", "disabled", "0:55", "1.5", "10", "1",
+            "10.0%", "2"]);
+        assert_eq!(t.rows[1], vec!["b", "unreachable"]);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let unauthorized = json!({"error": {"key": "unauthorized",
+            "message": "Unauthorized", "hint": null}, "took": 0.001});
+        assert_eq!(ok(Some(unauthorized.clone())), None);
+        assert_eq!(ok(Some(json!({"blocking": "enabled"}))),
+            Some(json!({"blocking": "enabled"})));
+
+        let snap = Snapshot {
+            blocking: Some(unauthorized),
+            ..Default::default()
+        };
+        assert!(!snap.reachable());
+        let t = servers_table(&["a".to_string()], &[snap], &[None], 0.0);
+        assert_eq!(t.rows[0], vec!["a", "unreachable"]);
+    }
+
+    #[test]
+    fn test_display_names() {
+        let servers = vec![Pihole::new("http://a.lan", ""),
+            Pihole::new("http://b.lan:8080", "")];
+        assert_eq!(display_names(&servers), vec!["a.lan", "b.lan"]);
+        let servers = vec![Pihole::new("http://h:81", ""),
+            Pihole::new("http://h:82", "")];
+        assert_eq!(display_names(&servers), vec!["h:81", "h:82"]);
+    }
+
+    #[test]
+    fn test_screen_fits() {
+        let mut t = Table::new(&["SERVER"]);
+        t.add_row(vec!["a-very-long-server-name".to_string()]);
+        let lines = screen("header", "", &[t], 10, 4);
+        assert_eq!(lines, vec!["header", "", "", "SERVER"]);
+        let lines = screen("a long header", "", &[], 6, 10);
+        assert_eq!(lines[0], "a long");
+    }
+}
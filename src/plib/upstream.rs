@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+/// Parse an upstream in the Pi-hole (dnsmasq) format, `IP` or `IP#port`,
+/// into the address to query.  IPv6 addresses are not bracketed
+pub fn parse_upstream(upstream: &str) -> Result<SocketAddr, String> {
+    let (ip_str, port_str) = match upstream.split_once('#') {
+        Some((i, p)) => (i, Some(p)),
+        None => (upstream, None),
+    };
+
+    let ip = match ip_str.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => {
+            return Err(format!("'{}' is not a valid IP address", ip_str));
+        },
+    };
+
+    let port = match port_str {
+        None => 53,
+        Some(p) => match p.parse::<u16>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("'{}' is not a valid port", p)),
+        },
+    };
+
+    return Ok(SocketAddr::new(ip, port));
+}
+
+/// Resolve `name` (an A record) against the upstream directly from this
+/// machine.  This succeeds if we get a NOERROR answer with at least one
+/// record in it
+pub fn check_upstream(
+    addr: &SocketAddr,
+    name: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let sock = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+
+    // Use the low bits of the clock as a query ID, which is plenty for
+    // matching the reply to this one query
+    let id = (chrono::Utc::now().timestamp_subsec_nanos() & 0xffff) as u16;
+    let query = build_query(id, name)?;
+    sock.send_to(&query, addr).map_err(|e| e.to_string())?;
+
+    // Stray or late datagrams, ex: the reply to an earlier query, are
+    // skipped until the timeout runs out rather than failing the check
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 512];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err("no response: timed out".to_string());
+        }
+        sock.set_read_timeout(Some(left)).map_err(|e| e.to_string())?;
+        let (n, from) = match sock.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) => return Err(format!("no response: {}", e)),
+        };
+        if from != *addr || n < 12 || buf[..2] != id.to_be_bytes() {
+            continue;
+        }
+
+        return check_response(id, &buf[..n]);
+    }
+}
+
+/// Build a DNS query packet for an A record with recursion desired
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>, String> {
+    let mut ret: Vec<u8> = vec![];
+    ret.extend_from_slice(&id.to_be_bytes());
+    // Flags (RD), QDCOUNT=1, ANCOUNT, NSCOUNT, ARCOUNT
+    ret.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("'{}' is not a valid name", name));
+        }
+        ret.push(label.len() as u8);
+        ret.extend_from_slice(label.as_bytes());
+    }
+    // Root label, QTYPE=A, QCLASS=IN
+    ret.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    return Ok(ret);
+}
+
+fn check_response(id: u16, resp: &[u8]) -> Result<(), String> {
+    if resp.len() < 12 || resp[..2] != id.to_be_bytes() {
+        return Err("mismatched response".to_string());
+    }
+
+    let rcode = resp[3] & 0x0f;
+    if rcode != 0 {
+        return Err(format!("server returned rcode {}", rcode));
+    }
+
+    let answers = u16::from_be_bytes([resp[6], resp[7]]);
+    if answers == 0 {
+        return Err("server returned no answers".to_string());
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_upstream() {
+        assert_eq!(
+            parse_upstream("1.1.1.1").unwrap(),
+            "1.1.1.1:53".parse::<SocketAddr>().unwrap(),
+        );
+        assert_eq!(
+            parse_upstream("127.0.0.1#5335").unwrap(),
+            "127.0.0.1:5335".parse::<SocketAddr>().unwrap(),
+        );
+        assert_eq!(
+            parse_upstream("2606:4700::1111#53").unwrap(),
+            "[2606:4700::1111]:53".parse::<SocketAddr>().unwrap(),
+        );
+        assert!(parse_upstream("dns.google").is_err());
+        assert!(parse_upstream("1.1.1.1#").is_err());
+        assert!(parse_upstream("1.1.1.1#0").is_err());
+        assert!(parse_upstream("1.1.1.1#70000").is_err());
+    }
+
+    #[test]
+    fn test_build_query() {
+        let q = build_query(0x1234, "pi-hole.net").unwrap();
+        assert_eq!(&q[..2], &[0x12, 0x34]);
+        assert_eq!(&q[12..20], b"\x07pi-hole");
+        assert_eq!(&q[q.len() - 5..], &[0, 0, 1, 0, 1]);
+        assert!(build_query(1, "bad..name").is_err());
+    }
+
+    #[test]
+    fn test_check_response() {
+        let mut resp = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        assert!(check_response(0x1234, &resp).is_ok());
+        assert!(check_response(0x4321, &resp).is_err());
+        // NXDOMAIN
+        resp[3] = 0x83;
+        assert!(check_response(0x1234, &resp).is_err());
+    }
+
+    #[test]
+    fn test_check_upstream_skips_other_ids() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            let mut resp = buf[..n].to_vec();
+            resp[2] = 0x81;
+            resp[3] = 0x80;
+            resp[7] = 1;
+            // A reply to some other query first, then the real one
+            let mut other = resp.clone();
+            other[0] ^= 0xff;
+            server.send_to(&other, from).unwrap();
+            server.send_to(&resp, from).unwrap();
+        });
+
+        assert_eq!(check_upstream(&addr, "pi-hole.net",
+            Duration::from_secs(2)), Ok(()));
+        responder.join().unwrap();
+    }
+}
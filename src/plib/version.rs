@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The components that make up a Pi-hole install, in display order
+pub const COMPONENTS: &[&str] = &["core", "web", "ftl", "docker"];
+
+/// The installed and available version for one component
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Component {
+    pub local: Option<String>,
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl Component {
+    /// Parse a component from the `info/version` response.  Docker has bare
+    /// version strings while the rest have objects with the details
+    fn from_value(v: &Value) -> Self {
+        let ver = |x: &Value| match x {
+            Value::String(s) => Some(s.clone()),
+            _ => x["version"].as_str().map(|s| s.to_string()),
+        };
+
+        return Self {
+            local: ver(&v["local"]).filter(|s| !s.is_empty()),
+            remote: ver(&v["remote"]).filter(|s| !s.is_empty()),
+            branch: v["local"]["branch"].as_str().map(|s| s.to_string()),
+        };
+    }
+
+    /// Return true if a newer version than the installed one is available
+    pub fn is_outdated(&self) -> bool {
+        return match (&self.local, &self.remote) {
+            (Some(l), Some(r)) => compare(l, r) == Ordering::Less,
+            _ => false,
+        };
+    }
+}
+
+/// The typed version info for a server
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Versions {
+    pub core: Component,
+    pub web: Component,
+    pub ftl: Component,
+    pub docker: Component,
+}
+
+impl Versions {
+    /// Parse the `info/version` response
+    pub fn from_value(resp: &Value) -> Result<Self, String> {
+        let v = match resp.get("version") {
+            Some(v) if v.is_object() => v,
+            _ => return Err("response has no version info".to_string()),
+        };
+
+        return Ok(Self {
+            core: Component::from_value(&v["core"]),
+            web: Component::from_value(&v["web"]),
+            ftl: Component::from_value(&v["ftl"]),
+            docker: Component::from_value(&v["docker"]),
+        });
+    }
+
+    /// Return a component by name, one of `COMPONENTS`
+    pub fn get(&self, name: &str) -> Option<&Component> {
+        return match name {
+            "core" => Some(&self.core),
+            "web" => Some(&self.web),
+            "ftl" => Some(&self.ftl),
+            "docker" => Some(&self.docker),
+            _ => None,
+        };
+    }
+
+    /// Return true if any installed component is behind
+    pub fn is_outdated(&self) -> bool {
+        return COMPONENTS.iter()
+            .any(|c| self.get(c).map(|x| x.is_outdated()).unwrap_or(false));
+    }
+}
+
+/// Return the components that don't have the same installed version on all
+/// the servers.  Components that a server doesn't have (ex: docker) are
+/// ignored for that server
+pub fn mismatched(versions: &[&Versions]) -> Vec<&'static str> {
+    let mut ret: Vec<&'static str> = vec![];
+    for c in COMPONENTS {
+        let mut locals: Vec<&str> = versions.iter()
+            .filter_map(|v| v.get(c).and_then(|x| x.local.as_deref()))
+            .collect();
+        locals.sort_unstable();
+        locals.dedup();
+        if locals.len() > 1 {
+            ret.push(c);
+        }
+    }
+
+    return ret;
+}
+
+/// Compare two version strings like "v6.0.4" or "2024.07.0" numerically by
+/// their dotted parts.  Anything that isn't numeric is compared as text
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let parts = |s: &str| -> Option<Vec<u64>> {
+        s.trim_start_matches('v')
+            .split(['.', '-'])
+            .map(|p| p.parse::<u64>().ok())
+            .collect()
+    };
+
+    return match (parts(a), parts(b)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        _ => a.cmp(b),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn resp(core: &str, core_remote: &str) -> Value {
+        return json!({"version": {
+            "core": {"local": {"branch": "master", "version": core,
+                "hash": "abc"}, "remote": {"version": core_remote}},
+            "web": {"local": {"branch": "master", "version": "v6.0.2"},
+                "remote": {"version": "v6.0.2"}},
+            "ftl": {"local": {"branch": "master", "version": "v6.0.4",
+                "date": "2025-03-04"}, "remote": {"version": "v6.1"}},
+            "docker": {"local": null, "remote": null},
+        }});
+    }
+
+    #[test]
+    fn test_from_value() {
+        let v = Versions::from_value(&resp("v6.0.5", "v6.0.5")).unwrap();
+        assert_eq!(v.core.local.as_deref(), Some("v6.0.5"));
+        assert_eq!(v.core.branch.as_deref(), Some("master"));
+        assert_eq!(v.docker, Component::default());
+        assert!(!v.core.is_outdated());
+        assert!(v.ftl.is_outdated());
+        assert!(v.is_outdated());
+        assert!(Versions::from_value(&json!({"error": {}})).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("v6.0.4", "v6.0.10"), Ordering::Less);
+        assert_eq!(compare("v6.1", "v6.0.9"), Ordering::Greater);
+        assert_eq!(compare("2024.07.0", "2024.07.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_mismatched() {
+        let a = Versions::from_value(&resp("v6.0.5", "v6.0.5")).unwrap();
+        let b = Versions::from_value(&resp("v6.0.4", "v6.0.5")).unwrap();
+        assert_eq!(mismatched(&[&a, &b]), vec!["core"]);
+        assert!(mismatched(&[&a, &a]).is_empty());
+    }
+}
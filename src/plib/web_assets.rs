@@ -0,0 +1,209 @@
+#![allow(dead_code)]
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use log::{debug, warn};
+
+/// The UI files, built into the binary, by their path under `static/`
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("index.html", include_bytes!("../../static/index.html")),
+    ("css/main.css", include_bytes!("../../static/css/main.css")),
+    ("js/main.js", include_bytes!("../../static/js/main.js")),
+];
+
+/// A static file to serve, with its type and ETag
+pub struct Asset {
+    pub body: Cow<'static, [u8]>,
+    pub mime: &'static str,
+    pub etag: String,
+}
+
+impl Asset {
+    pub fn new(path: &str, body: Cow<'static, [u8]>) -> Self {
+        let etag = etag(&body);
+        return Self {
+            body,
+            mime: mime_type(path),
+            etag,
+        };
+    }
+
+    /// Whether an `If-None-Match` header value matches this file, so the
+    /// browser's copy is still good
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        return if_none_match.split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == self.etag);
+    }
+}
+
+/// Get a file that's built in to the binary
+pub fn embedded(path: &str) -> Option<Asset> {
+    return EMBEDDED.iter()
+        .find(|(p, _)| *p == path)
+        .map(|(p, body)| Asset::new(p, Cow::Borrowed(*body)));
+}
+
+/// Get a file from under the `root` directory.  The path has to stay
+/// inside of it, even through symlinks, and be a regular file
+pub fn from_dir(root: &Path, path: &str) -> Option<Asset> {
+    let root = match root.canonicalize() {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Invalid static_dir {}: {}", root.display(), e);
+            return None;
+        },
+    };
+    let fname = match root.join(path).canonicalize() {
+        Ok(f) => f,
+        Err(e) => {
+            debug!("No static file {}: {}", path, e);
+            return None;
+        },
+    };
+    if !fname.starts_with(&root) || !fname.is_file() {
+        warn!("Not serving {}, it's not a file inside of {}",
+            fname.display(), root.display());
+        return None;
+    }
+
+    return match fs::read(&fname) {
+        Ok(body) => Some(Asset::new(path, Cow::Owned(body))),
+        Err(e) => {
+            warn!("Failed to read {}: {}", fname.display(), e);
+            None
+        },
+    };
+}
+
+/// Turn the (percent encoded) segments of a URL path into a relative file
+/// path, or None if any of them could climb out of the directory or are
+/// otherwise odd
+pub fn clean_path(segments: &[&str]) -> Option<String> {
+    let mut parts = vec![];
+    for seg in segments {
+        let seg = percent_decode(seg)?;
+        let bad = seg.is_empty()
+            || seg.starts_with('.')
+            || seg.contains(['/', '\\', '\0']);
+        if bad {
+            return None;
+        }
+        parts.push(seg);
+    }
+    if parts.is_empty() {
+        return None;
+    }
+
+    return Some(parts.join("/"));
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            ret.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    return String::from_utf8(ret).ok();
+}
+
+/// The Content-Type for a file, by its extension
+pub fn mime_type(path: &str) -> &'static str {
+    let ext = path.rsplit_once('.')
+        .map(|(_, e)| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    return match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        _ => "application/octet-stream",
+    };
+}
+
+/// A quoted ETag from the FNV-1a hash of the contents
+pub fn etag(body: &[u8]) -> String {
+    let hash = body.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+
+    return format!("\"{:016x}\"", hash);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded() {
+        let idx = embedded("index.html").unwrap();
+        assert_eq!(idx.mime, "text/html; charset=utf-8");
+        assert!(idx.body.starts_with(b"<!DOCTYPE html>"));
+        assert_eq!(embedded("js/main.js").unwrap().mime,
+            "text/javascript; charset=utf-8");
+        assert!(embedded("../Cargo.toml").is_none());
+    }
+
+    #[test]
+    fn test_clean_path() {
+        assert_eq!(clean_path(&["js", "main.js"]), Some("js/main.js".into()));
+        assert_eq!(clean_path(&["a%20b.css"]), Some("a b.css".into()));
+        assert_eq!(clean_path(&["..", "Cargo.toml"]), None);
+        assert_eq!(clean_path(&["%2e%2e", "Cargo.toml"]), None);
+        assert_eq!(clean_path(&["js", "..%2f..%2fetc"]), None);
+        assert_eq!(clean_path(&[".git", "config"]), None);
+        assert_eq!(clean_path(&["js", ""]), None);
+        assert_eq!(clean_path(&["a%5c..%5cb"]), None);
+        assert_eq!(clean_path(&["bad%zz"]), None);
+        assert_eq!(clean_path(&[]), None);
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("static");
+        let js = from_dir(&root, "js/main.js").unwrap();
+        assert_eq!(js.etag, embedded("js/main.js").unwrap().etag);
+        assert!(from_dir(&root, "../Cargo.toml").is_none());
+        assert!(from_dir(&root, "js").is_none());
+        assert!(from_dir(&root, "nope.js").is_none());
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type("img/logo.PNG"), "image/png");
+        assert_eq!(mime_type("fonts/a.woff2"), "font/woff2");
+        assert_eq!(mime_type("README"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_etag() {
+        let a = Asset::new("a.css", Cow::Borrowed(b"body {}"));
+        assert_eq!(a.etag, etag(b"body {}"));
+        assert_ne!(a.etag, etag(b"body { }"));
+        assert!(a.matches(&a.etag));
+        assert!(a.matches(&format!("\"x\", W/{}", a.etag)));
+        assert!(a.matches("*"));
+        assert!(!a.matches("\"x\""));
+    }
+}
@@ -0,0 +1,380 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use configparser::ini::Ini;
+
+/// The name of the session cookie for the HTML pages
+pub const SESSION_COOKIE: &str = "pi_ctl_session";
+/// The header the pages send their CSRF token in
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Who a request was authenticated as
+#[derive(Clone, Debug, PartialEq)]
+pub enum Identity {
+    /// A user from `[users]`, by HTTP Basic or a session cookie
+    User(String),
+    /// A bearer token from `[tokens]`, by its name
+    Token(String),
+    /// Authentication is turned off
+    Anonymous,
+}
+
+/// What someone is allowed to do, from the `[roles]` and `[token_roles]`
+/// sections.  Anyone without a role is an admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// Can only look at the servers
+    Viewer,
+    /// Can also enable blocking, and disable it for up to `max_disable`
+    /// seconds, if there's a limit
+    Toggler { max_disable: Option<u64> },
+    /// Can do anything
+    Admin,
+}
+
+impl Role {
+    /// Parse a role from the config, ex: "viewer", "admin", "toggler" or
+    /// "toggler:900" for a toggler that can disable for up to 15 minutes
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim().to_lowercase();
+        return match s.split_once(':') {
+            None if s == "viewer" => Ok(Role::Viewer),
+            None if s == "admin" => Ok(Role::Admin),
+            None if s == "toggler" => Ok(Role::Toggler { max_disable: None }),
+            Some(("toggler", secs)) => match secs.trim().parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Role::Toggler { max_disable: Some(n) }),
+                _ => Err(format!("Invalid max disable time in role '{}'", s)),
+            },
+            _ => Err(format!("Unknown role '{}', it must be viewer, \
+                toggler[:<secs>] or admin", s)),
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Role::Viewer => "viewer",
+            Role::Toggler { .. } => "toggler",
+            Role::Admin => "admin",
+        };
+    }
+
+    /// Whether this role can enable and disable blocking at all
+    pub fn can_toggle(&self) -> bool {
+        return *self != Role::Viewer;
+    }
+
+    /// The longest this role can disable blocking for, None if there's no
+    /// limit
+    pub fn max_disable(&self) -> Option<u64> {
+        return match self {
+            Role::Toggler { max_disable } => *max_disable,
+            _ => None,
+        };
+    }
+
+    /// Check whether this role can disable blocking for `secs`
+    pub fn check_disable(&self, secs: u64) -> Result<(), String> {
+        if !self.can_toggle() {
+            return Err("Not allowed to disable blocking".to_string());
+        }
+        return match self.max_disable() {
+            Some(max) if secs == 0 || secs > max => Err(format!(
+                "Not allowed to disable blocking for more than {} seconds",
+                max)),
+            _ => Ok(()),
+        };
+    }
+}
+
+struct Session {
+    user: String,
+    csrf: String,
+    expires: Instant,
+}
+
+/// The users, tokens, roles and login sessions for the web server, from the
+/// `[auth]`, `[users]`, `[tokens]`, `[roles]` and `[token_roles]` sections of
+/// the web config
+pub struct Auth {
+    pub enabled: bool,
+    pub session_ttl: Duration,
+    users: HashMap<String, String>,
+    tokens: Vec<(String, String)>,
+    roles: HashMap<String, Role>,
+    token_roles: HashMap<String, Role>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Auth {
+    pub fn from_ini(conf: &Ini) -> Result<Self, String> {
+        let enabled = conf.getbool("auth", "enabled")?.unwrap_or(true);
+        let ttl = conf.getuint("auth", "session_ttl")?.unwrap_or(86400);
+        let section = |name: &str| -> Vec<(String, String)> {
+            let mut ret: Vec<(String, String)> = conf.get_map_ref().get(name)
+                .map(|m| m.iter()
+                    .filter_map(|(k, v)| v.clone().map(|v| (k.clone(), v)))
+                    .collect())
+                .unwrap_or_default();
+            ret.sort();
+            ret
+        };
+
+        let users = section("users");
+        for (user, hash) in &users {
+            if !is_supported_hash(hash) {
+                return Err(format!("The password for user '{}' must be a \
+                    bcrypt or argon2 hash", user));
+            }
+        }
+        let tokens = section("tokens");
+        if enabled && users.is_empty() && tokens.is_empty() {
+            return Err("Authentication is enabled, but there are no [users] \
+                or [tokens] configured".to_string());
+        }
+        let roles = |name: &str, known: &[(String, String)]| {
+            let mut ret = HashMap::new();
+            for (who, role) in section(name) {
+                if !known.iter().any(|(k, _)| *k == who) {
+                    return Err(format!("[{}] has a role for '{}', who isn't \
+                        configured", name, who));
+                }
+                let role = Role::parse(&role)
+                    .map_err(|e| format!("[{}] {}: {}", name, who, e))?;
+                ret.insert(who, role);
+            }
+            Ok(ret)
+        };
+        let user_roles = roles("roles", &users)?;
+        let token_roles = roles("token_roles", &tokens)?;
+
+        return Ok(Self {
+            enabled,
+            session_ttl: Duration::from_secs(ttl),
+            users: users.into_iter().collect(),
+            tokens,
+            roles: user_roles,
+            token_roles,
+            sessions: Mutex::new(HashMap::new()),
+        });
+    }
+
+    /// Check a user's password.  User names are case insensitive, like all
+    /// the keys in the config
+    pub fn check_password(&self, user: &str, passwd: &str) -> bool {
+        return match self.users.get(&user.to_lowercase()) {
+            Some(hash) => verify_hash(passwd, hash),
+            None => false,
+        };
+    }
+
+    /// The role for someone who's been authenticated
+    pub fn role(&self, id: &Identity) -> Role {
+        let role = match id {
+            Identity::User(u) => self.roles.get(&u.to_lowercase()),
+            Identity::Token(t) => self.token_roles.get(t),
+            Identity::Anonymous => None,
+        };
+
+        return role.copied().unwrap_or(Role::Admin);
+    }
+
+    /// Return the name of the bearer token, if it's a configured one
+    pub fn check_token(&self, token: &str) -> Option<String> {
+        return self.tokens.iter()
+            .find(|(_, t)| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .map(|(name, _)| name.clone());
+    }
+
+    /// Start a login session for the user and return its ID for the cookie
+    pub fn start_session(&self, user: &str) -> String {
+        let id = random_token();
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(id.clone(), Session {
+            user: user.to_lowercase(),
+            csrf: random_token(),
+            expires: now + self.session_ttl,
+        });
+
+        return id;
+    }
+
+    /// Return the user for a session ID, if the session hasn't expired
+    pub fn session_user(&self, id: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        return sessions.get(id)
+            .filter(|s| s.expires > Instant::now())
+            .map(|s| s.user.clone());
+    }
+
+    /// Return the CSRF token for a session, for the pages to send back
+    /// with any changes
+    pub fn csrf_token(&self, id: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        return sessions.get(id)
+            .filter(|s| s.expires > Instant::now())
+            .map(|s| s.csrf.clone());
+    }
+
+    /// Check the CSRF token sent with a change made with a session cookie
+    pub fn check_csrf(&self, id: &str, token: &str) -> bool {
+        return match self.csrf_token(id) {
+            Some(t) => constant_time_eq(t.as_bytes(), token.as_bytes()),
+            None => false,
+        };
+    }
+
+    pub fn end_session(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+/// Check a password against a bcrypt ("$2b$...") or argon2 ("$argon2id$...")
+/// hash
+pub fn verify_hash(passwd: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        return match PasswordHash::new(hash) {
+            Ok(h) => Argon2::default()
+                .verify_password(passwd.as_bytes(), &h)
+                .is_ok(),
+            Err(_) => false,
+        };
+    }
+
+    return bcrypt::verify(passwd, hash).unwrap_or(false);
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    return hash.starts_with("$argon2") || hash.starts_with("$2");
+}
+
+/// Hash a password with bcrypt for the `[users]` section
+pub fn hash_password(passwd: &str) -> Result<String, String> {
+    return bcrypt::hash(passwd, bcrypt::DEFAULT_COST).map_err(|e| e.to_string());
+}
+
+/// A random 256 bit hex token, ex: for session IDs
+pub fn random_token() -> String {
+    let mut buf = [0u8; 32];
+    getrandom::getrandom(&mut buf).expect("No random numbers available");
+    return buf.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/// Find a cookie by name in the `Cookie` header values
+pub fn find_cookie(headers: &[String], name: &str) -> Option<String> {
+    return headers.iter()
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string());
+}
+
+/// Compare secrets without leaking how much of them matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    return a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    fn conf(extra: &str) -> Ini {
+        let mut ini = Ini::new();
+        ini.read(format!("[main]\nbind_to = 0.0.0.0:9100\n{}", extra)).unwrap();
+        return ini;
+    }
+
+    #[test]
+    fn test_passwords() {
+        let bc = bcrypt::hash("hunter2", 4).unwrap();
+        let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+        let ar = Argon2::default().hash_password(b"swordfish", &salt)
+            .unwrap().to_string();
+        let auth = Auth::from_ini(&conf(&format!(
+            "[users]\nJay = {}\nkim = {}\n", bc, ar))).unwrap();
+        assert!(auth.check_password("jay", "hunter2"));
+        assert!(!auth.check_password("jay", "hunter3"));
+        assert!(auth.check_password("kim", "swordfish"));
+        assert!(!auth.check_password("nobody", "swordfish"));
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(Auth::from_ini(&conf("")).is_err());
+        assert!(Auth::from_ini(&conf("[users]\njay = plaintext\n")).is_err());
+        let auth = Auth::from_ini(&conf("[auth]\nenabled = false\n")).unwrap();
+        assert!(!auth.enabled);
+    }
+
+    #[test]
+    fn test_roles() {
+        assert_eq!(Role::parse("Viewer"), Ok(Role::Viewer));
+        assert_eq!(Role::parse("toggler"),
+            Ok(Role::Toggler { max_disable: None }));
+        assert_eq!(Role::parse("toggler: 900"),
+            Ok(Role::Toggler { max_disable: Some(900) }));
+        assert_eq!(Role::parse("toggler:0"),
+            Err("Invalid max disable time in role 'toggler:0'".to_string()));
+        assert_eq!(Role::parse("root"), Err("Unknown role 'root', it must be \
+            viewer, toggler[:<secs>] or admin".to_string()));
+
+        let bc = bcrypt::hash("hunter2", 4).unwrap();
+        let auth = Auth::from_ini(&conf(&format!(
+            "[users]\njay = {0}\nkid = {0}\nguest = {0}\n\
+            [tokens]\nhass = abc123\ngrafana = def456\n\
+            [roles]\nkid = toggler:900\nguest = viewer\n\
+            [token_roles]\ngrafana = viewer\n", bc))).unwrap();
+        assert_eq!(auth.role(&Identity::User("jay".into())), Role::Admin);
+        assert_eq!(auth.role(&Identity::User("Kid".into())).max_disable(),
+            Some(900));
+        assert_eq!(auth.role(&Identity::Token("grafana".into())), Role::Viewer);
+        assert_eq!(auth.role(&Identity::Token("hass".into())), Role::Admin);
+        assert_eq!(auth.role(&Identity::Anonymous), Role::Admin);
+
+        let kid = auth.role(&Identity::User("kid".into()));
+        assert!(kid.check_disable(900).is_ok());
+        assert!(kid.check_disable(901).is_err());
+        assert!(kid.check_disable(0).is_err());
+        assert!(Role::Viewer.check_disable(1).is_err());
+        assert!(Role::Admin.check_disable(0).is_ok());
+
+        assert!(Auth::from_ini(&conf(&format!(
+            "[users]\njay = {}\n[roles]\nbob = viewer\n", bc))).is_err());
+        assert!(Auth::from_ini(&conf(&format!(
+            "[users]\njay = {}\n[roles]\njay = boss\n", bc))).is_err());
+    }
+
+    #[test]
+    fn test_tokens_and_sessions() {
+        let auth = Auth::from_ini(&conf("[tokens]\nhass = abc123\n")).unwrap();
+        assert_eq!(auth.check_token("abc123"), Some("hass".to_string()));
+        assert_eq!(auth.check_token("abc124"), None);
+
+        let id = auth.start_session("Jay");
+        assert_eq!(id.len(), 64);
+        assert_eq!(auth.session_user(&id), Some("jay".to_string()));
+        let csrf = auth.csrf_token(&id).unwrap();
+        assert_ne!(csrf, id);
+        assert!(auth.check_csrf(&id, &csrf));
+        assert!(!auth.check_csrf(&id, &id));
+        auth.end_session(&id);
+        assert_eq!(auth.session_user(&id), None);
+        assert!(!auth.check_csrf(&id, &csrf));
+    }
+
+    #[test]
+    fn test_find_cookie() {
+        let headers = vec!["a=1; pi_ctl_session=xyz".to_string()];
+        assert_eq!(find_cookie(&headers, SESSION_COOKIE), Some("xyz".to_string()));
+        assert_eq!(find_cookie(&headers, "b"), None);
+    }
+}
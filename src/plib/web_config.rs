@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 extern crate configparser;
 
 use configparser::ini::Ini;
@@ -5,9 +7,9 @@ use configparser::ini::Ini;
 pub fn get_config(path: &str) -> Ini {
     let mut conf = Ini::new();
 
-    conf.load(path).expect(
-        &format!("Failed to load config from path: {}", path)
-    );
+    if let Err(e) = conf.load(path) {
+        panic!("Failed to load config from path: {}: {}", path, e);
+    }
 
     return conf;
 }
\ No newline at end of file
@@ -0,0 +1,376 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+use iron::response::WriteBody;
+use serde::Serialize;
+use serde_json::{json, Value};
+use log::debug;
+use super::web_sessions::ServerResult;
+
+/// How many events to keep for clients that are a little behind.  Clients
+/// further behind than this get a new status instead
+const BACKLOG: usize = 64;
+/// Send a comment this often when nothing happens, to find out when the
+/// browser has gone away
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// The state of one server, as sent in the "status" events.  The summary is
+/// also sent on its own, in "stats" events, since it changes all the time
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ServerState {
+    pub server: String,
+    pub reachable: bool,
+    pub blocking: Option<String>,
+    pub timer: Option<f64>,
+    pub summary: Option<Value>,
+    pub error: Option<String>,
+}
+
+impl ServerState {
+    /// The state from a poll with `{"blocking": ..., "summary": ...}` data
+    pub fn from_result(r: &ServerResult) -> Self {
+        let data = r.data.as_ref();
+        return Self {
+            server: r.server.clone(),
+            reachable: r.is_ok(),
+            blocking: data
+                .and_then(|d| d["blocking"]["blocking"].as_str())
+                .map(|b| b.to_string()),
+            timer: data.and_then(|d| d["blocking"]["timer"].as_f64()),
+            summary: data
+                .map(|d| d["summary"].clone())
+                .filter(|s| !s.is_null()),
+            error: r.error.clone(),
+        };
+    }
+
+    /// Whether the status changed, ignoring the timer counting down and the
+    /// summary stats
+    fn same_as(&self, other: &Self) -> bool {
+        return self.server == other.server
+            && self.reachable == other.reachable
+            && self.blocking == other.blocking
+            && self.timer.is_some() == other.timer.is_some()
+            && self.error == other.error;
+    }
+}
+
+/// The "change" events between two polls of a server: blocking turned on
+/// or off (or back on by its timer) and the server going away or coming back
+pub fn changes(prev: &ServerState, cur: &ServerState) -> Vec<Value> {
+    let mut ret = vec![];
+    let event = |change: &str| json!({
+        "server": cur.server,
+        "change": change,
+        "blocking": cur.blocking,
+        "timer": cur.timer,
+        "error": cur.error,
+    });
+
+    if prev.reachable && !cur.reachable {
+        ret.push(event("unreachable"));
+        return ret;
+    }
+    if !prev.reachable && cur.reachable {
+        ret.push(event("reachable"));
+    }
+
+    match (prev.blocking.as_deref(), cur.blocking.as_deref()) {
+        (Some("disabled"), Some("enabled")) if prev.timer.is_some() => {
+            ret.push(event("timer-expired"));
+        },
+        (Some(p), Some(c)) if p != c => ret.push(event(c)),
+        _ => (),
+    }
+
+    return ret;
+}
+
+struct HubState {
+    /// The ID of the last event
+    seq: u64,
+    servers: Vec<ServerState>,
+    events: VecDeque<(u64, String)>,
+    clients: usize,
+}
+
+/// Polls the servers on one interval for all the connected browsers, and
+/// hands out the changes as server-sent events.  A "status" event goes out
+/// when a server's state changes, and otherwise a "stats" event when only
+/// the summaries did, which on a busy network is most polls
+pub struct EventHub {
+    state: Mutex<HubState>,
+    cond: Condvar,
+    interval: Duration,
+    max_clients: usize,
+}
+
+impl EventHub {
+    pub fn new(interval: Duration, max_clients: usize) -> Arc<Self> {
+        return Arc::new(Self {
+            state: Mutex::new(HubState {
+                seq: 0,
+                servers: vec![],
+                events: VecDeque::new(),
+                clients: 0,
+            }),
+            cond: Condvar::new(),
+            interval,
+            max_clients,
+        });
+    }
+
+    /// Start polling with `poll`, but only while there are clients
+    /// connected
+    pub fn start<F>(self: &Arc<Self>, poll: F)
+    where
+        F: Fn() -> Vec<ServerResult> + Send + 'static,
+    {
+        let hub = self.clone();
+        thread::spawn(move || loop {
+            {
+                let mut state = hub.lock();
+                while state.clients == 0 {
+                    state = hub.cond.wait(state)
+                        .unwrap_or_else(|p| p.into_inner());
+                }
+            }
+
+            let servers = poll().iter().map(ServerState::from_result).collect();
+            hub.update(servers);
+            thread::sleep(hub.interval);
+        });
+    }
+
+    /// Record a new poll of the servers, and queue the events for it
+    pub fn update(&self, servers: Vec<ServerState>) {
+        let mut state = self.lock();
+        let first = state.servers.is_empty();
+        let mut events = vec![];
+        for cur in &servers {
+            if let Some(prev) = state.servers.iter().find(|p| p.server == cur.server) {
+                events.extend(changes(prev, cur).into_iter()
+                    .map(|c| ("change", c)));
+            }
+        }
+        let changed = first || servers.len() != state.servers.len()
+            || servers.iter().zip(&state.servers).any(|(a, b)| !a.same_as(b));
+        let new_stats = servers.iter().zip(&state.servers)
+            .any(|(a, b)| a.summary != b.summary);
+        if changed {
+            events.push(("status", json!({ "servers": servers })));
+        } else if new_stats {
+            let stats: Vec<Value> = servers.iter()
+                .map(|s| json!({"server": s.server, "summary": s.summary}))
+                .collect();
+            events.push(("stats", json!({ "servers": stats })));
+        }
+        state.servers = servers;
+
+        for (name, data) in events {
+            state.seq += 1;
+            let seq = state.seq;
+            state.events.push_back((seq, format_event(seq, name, &data)));
+            if state.events.len() > BACKLOG {
+                state.events.pop_front();
+            }
+        }
+        self.cond.notify_all();
+    }
+
+    /// Add a client, unless there are too many already
+    pub fn subscribe(self: &Arc<Self>) -> Option<EventStream> {
+        let mut state = self.lock();
+        if state.clients >= self.max_clients {
+            return None;
+        }
+        state.clients += 1;
+        debug!("Event stream client connected, {} now", state.clients);
+        self.cond.notify_all();
+
+        return Some(EventStream { hub: self.clone(), last: None });
+    }
+
+    fn unsubscribe(&self) {
+        let mut state = self.lock();
+        state.clients -= 1;
+        debug!("Event stream client gone, {} left", state.clients);
+        if state.clients == 0 {
+            // Don't hand out a stale status when polling starts again
+            state.servers.clear();
+            state.events.clear();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HubState> {
+        return self.state.lock().unwrap_or_else(|p| p.into_inner());
+    }
+
+    /// Wait for the events after `last`, or the current status if this is
+    /// a new client or it fell too far behind.  An empty string means it
+    /// timed out without anything new
+    fn next(&self, last: &mut Option<u64>) -> String {
+        let mut state = self.lock();
+        let prev = *last;
+        let caught_up = |s: &HubState| match prev {
+            Some(l) => s.seq == l,
+            None => s.servers.is_empty(),
+        };
+        state = self.cond.wait_timeout_while(state, KEEPALIVE, |s| caught_up(s))
+            .unwrap_or_else(|p| p.into_inner()).0;
+        if caught_up(&state) {
+            return "".to_string();
+        }
+
+        let missed = match prev {
+            Some(l) => state.events.front().map(|(id, _)| *id > l + 1)
+                .unwrap_or(true),
+            None => true,
+        };
+        let ret = if missed {
+            format_event(state.seq, "status", &json!({
+                "servers": state.servers,
+            }))
+        } else {
+            state.events.iter()
+                .filter(|(id, _)| Some(*id) > prev)
+                .map(|(_, e)| e.as_str())
+                .collect()
+        };
+        *last = Some(state.seq);
+
+        return ret;
+    }
+}
+
+/// The response body for one client, which streams the events until the
+/// client goes away
+pub struct EventStream {
+    hub: Arc<EventHub>,
+    last: Option<u64>,
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        // Have browsers wait a bit before reconnecting
+        res.write_all(b"retry: 5000\n\n")?;
+        res.flush()?;
+        loop {
+            let events = self.hub.next(&mut self.last);
+            if events.is_empty() {
+                res.write_all(b": keepalive\n\n")?;
+            } else {
+                res.write_all(events.as_bytes())?;
+            }
+            res.flush()?;
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.hub.unsubscribe();
+    }
+}
+
+fn format_event(id: u64, name: &str, data: &Value) -> String {
+    return format!("id: {}\nevent: {}\ndata: {}\n\n", id, name, data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(blocking: &str, timer: Option<f64>) -> ServerState {
+        return with_total(blocking, timer, 10);
+    }
+
+    fn with_total(blocking: &str, timer: Option<f64>, total: i64) -> ServerState {
+        return ServerState::from_result(&ServerResult {
+            server: "http://pihole".to_string(),
+            data: Some(json!({
+                "blocking": {"blocking": blocking, "timer": timer},
+                "summary": {"queries": {"total": total}},
+            })),
+            error: None,
+        });
+    }
+
+    fn down() -> ServerState {
+        return ServerState::from_result(&ServerResult {
+            server: "http://pihole".to_string(),
+            data: None,
+            error: Some("No response from the server".to_string()),
+        });
+    }
+
+    fn change_names(prev: &ServerState, cur: &ServerState) -> Vec<String> {
+        return changes(prev, cur).iter()
+            .map(|c| c["change"].as_str().unwrap().to_string())
+            .collect();
+    }
+
+    #[test]
+    fn test_changes() {
+        let on = state("enabled", None);
+        assert_eq!(on.blocking.as_deref(), Some("enabled"));
+        assert_eq!(on.summary, Some(json!({"queries": {"total": 10}})));
+        assert!(change_names(&on, &on).is_empty());
+        assert_eq!(change_names(&on, &state("disabled", Some(30.0))),
+            vec!["disabled"]);
+        assert_eq!(change_names(&state("disabled", Some(1.0)), &on),
+            vec!["timer-expired"]);
+        assert_eq!(change_names(&state("disabled", None), &on),
+            vec!["enabled"]);
+        assert_eq!(change_names(&on, &down()), vec!["unreachable"]);
+        assert_eq!(change_names(&down(), &state("disabled", None)),
+            vec!["reachable"]);
+        assert_eq!(changes(&down(), &state("disabled", None))[0]["blocking"],
+            json!("disabled"));
+        assert!(change_names(&down(), &down()).is_empty());
+    }
+
+    #[test]
+    fn test_hub() {
+        let hub = EventHub::new(Duration::from_secs(1), 1);
+        let mut stream = hub.subscribe().unwrap();
+        assert!(hub.subscribe().is_none());
+
+        hub.update(vec![state("enabled", None)]);
+        let ev = hub.next(&mut stream.last);
+        assert!(ev.starts_with("id: 1\nevent: status\n"));
+
+        // Only the timer counting down isn't worth an event
+        hub.update(vec![state("disabled", Some(30.0))]);
+        hub.update(vec![state("disabled", Some(25.0))]);
+        let ev = hub.next(&mut stream.last);
+        assert!(ev.starts_with("id: 2\nevent: change\n"));
+        assert!(ev.contains("\"change\":\"disabled\""));
+        assert!(ev.contains("id: 3\nevent: status\n"));
+        assert!(!ev.contains("id: 4"));
+
+        // New stats alone don't resend the whole status
+        hub.update(vec![with_total("disabled", Some(20.0), 15)]);
+        let ev = hub.next(&mut stream.last);
+        assert!(ev.starts_with("id: 4\nevent: stats\n"));
+        assert!(ev.contains("\"total\":15"));
+        assert!(!ev.contains("\"blocking\""));
+        hub.update(vec![with_total("disabled", Some(15.0), 15)]);
+        hub.update(vec![with_total("enabled", None, 20)]);
+        let ev = hub.next(&mut stream.last);
+        assert!(ev.starts_with("id: 5\nevent: change\n"));
+        assert!(ev.contains("id: 6\nevent: status\n"));
+        assert!(!ev.contains("event: stats"));
+
+        drop(stream);
+        assert_eq!(hub.lock().clients, 0);
+        assert!(hub.lock().servers.is_empty());
+        assert!(hub.subscribe().is_some());
+    }
+}
@@ -0,0 +1,309 @@
+#![allow(dead_code)]
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use serde_json::Value;
+use log::{debug, info, warn};
+use super::config::PiServer;
+use super::pihole::Pihole;
+
+/// Get a new session when the current one has less than this left
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+/// Used if the server doesn't say how long its sessions last
+const DEFAULT_VALIDITY: u64 = 300;
+
+/// The result of calling one server, either its data or why it failed
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ServerResult {
+    pub server: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ServerResult {
+    pub fn is_ok(&self) -> bool {
+        return self.error.is_none();
+    }
+}
+
+struct PiSession {
+    pihole: Pihole,
+    expires: Option<Instant>,
+    validity: Duration,
+}
+
+impl PiSession {
+    /// Authenticate if there's no session yet, or it's about to expire
+    fn ensure_auth(&mut self) -> Result<(), String> {
+        let fresh = self.expires
+            .map(|e| e > Instant::now() + REFRESH_MARGIN)
+            .unwrap_or(false);
+        if fresh {
+            return Ok(());
+        }
+
+        return self.login();
+    }
+
+    fn login(&mut self) -> Result<(), String> {
+        debug!("Authenticating with {}", self.pihole.base_url);
+        self.expires = None;
+        // The server only allows so many sessions, so give the old one back
+        // rather than leaving it to expire
+        if !self.pihole.logout() {
+            debug!("Couldn't end the old session with {}",
+                self.pihole.base_url);
+        }
+        let res = match self.pihole.auth() {
+            Some(r) => r,
+            None => return Err("Failed to connect for authentication"
+                .to_string()),
+        };
+        if let Some(e) = api_error(&res) {
+            return Err(format!("Authentication failed: {}", e));
+        }
+
+        let sess = &res["session"];
+        if sess["valid"].as_bool() != Some(true) {
+            return Err("Authentication failed: wrong password".to_string());
+        }
+        let validity = sess["validity"].as_u64().unwrap_or(DEFAULT_VALIDITY);
+        self.validity = Duration::from_secs(validity);
+        self.expires = Some(Instant::now() + self.validity);
+
+        return Ok(());
+    }
+
+    /// Run the call, getting a new session and trying once more if the
+    /// server says the one we have isn't valid anymore
+    fn call<F>(&mut self, f: &F) -> Result<Value, String>
+    where
+        F: Fn(&Pihole) -> Option<Value>,
+    {
+        self.ensure_auth()?;
+        let mut res = f(&self.pihole);
+        if res.as_ref().map(is_unauthorized).unwrap_or(false) {
+            info!("Session for {} was rejected, authenticating again",
+                self.pihole.base_url);
+            self.login()?;
+            res = f(&self.pihole);
+        }
+
+        let res = match res {
+            Some(r) => r,
+            None => {
+                // Don't trust the session after a failed connection
+                self.expires = None;
+                return Err("No response from the server".to_string());
+            },
+        };
+        if let Some(e) = api_error(&res) {
+            return Err(e);
+        }
+
+        // The server extends the session on every request
+        self.expires = Some(Instant::now() + self.validity);
+        return Ok(res);
+    }
+}
+
+struct Entry {
+    url: String,
+    name: String,
+    sess: Mutex<PiSession>,
+}
+
+/// Keeps a session for each of the servers, shared between the request
+/// handlers of the web server.  Each server has its own lock, so the calls
+/// to the different servers run in parallel
+pub struct PiSessions {
+    servers: Vec<Entry>,
+}
+
+impl PiSessions {
+    pub fn from_cfg(servers: &[PiServer]) -> Self {
+        return Self {
+            servers: servers.iter()
+                .map(|s| {
+                    let pihole = Pihole::from_cfg(s);
+                    Entry {
+                        url: pihole.base_url.clone(),
+                        name: pihole.name().to_string(),
+                        sess: Mutex::new(PiSession {
+                            pihole,
+                            expires: None,
+                            validity: Duration::from_secs(DEFAULT_VALIDITY),
+                        }),
+                    }
+                })
+                .collect(),
+        };
+    }
+
+    /// The base URLs of the servers, in config order
+    pub fn names(&self) -> Vec<&str> {
+        return self.servers.iter().map(|e| e.url.as_str()).collect();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.servers.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.servers.is_empty();
+    }
+
+    /// Run a call against all the servers at once, authenticating as
+    /// needed, and return the result for each server in config order
+    pub fn run<F>(&self, f: F) -> Vec<ServerResult>
+    where
+        F: Fn(&Pihole) -> Option<Value> + Sync,
+    {
+        return run_entries(self.servers.iter().collect(), &f);
+    }
+
+    /// Like `run()`, but only on the `targets`, given by their base URL or
+    /// host name.  All of the servers if there are no targets
+    pub fn run_on<F>(
+        &self,
+        targets: Option<&[String]>,
+        f: F,
+    ) -> Result<Vec<ServerResult>, String>
+    where
+        F: Fn(&Pihole) -> Option<Value> + Sync,
+    {
+        let targets = match targets {
+            Some(t) => t,
+            None => return Ok(self.run(f)),
+        };
+        for t in targets {
+            if !self.servers.iter().any(|e| e.matches(t)) {
+                return Err(format!("Unknown server: {}", t));
+            }
+        }
+
+        let entries = self.servers.iter()
+            .filter(|e| targets.iter().any(|t| e.matches(t)))
+            .collect();
+        return Ok(run_entries(entries, &f));
+    }
+}
+
+impl Entry {
+    fn matches(&self, target: &str) -> bool {
+        let target = target.trim_end_matches('/');
+        return self.url == target || self.name.eq_ignore_ascii_case(target);
+    }
+}
+
+fn run_entries<F>(entries: Vec<&Entry>, f: &F) -> Vec<ServerResult>
+where
+    F: Fn(&Pihole) -> Option<Value> + Sync,
+{
+    return thread::scope(|scope| {
+        let handles: Vec<_> = entries.iter()
+            .map(|e| scope.spawn(move || {
+                let mut sess = match e.sess.lock() {
+                    Ok(g) => g,
+                    // A panic in another request doesn't make the
+                    // session itself any less usable
+                    Err(p) => p.into_inner(),
+                };
+                let res = sess.call(f);
+                if let Err(err) = &res {
+                    warn!("Call to {} failed: {}", e.url, err);
+                }
+                to_result(&e.url, res)
+            }))
+            .collect();
+        handles.into_iter()
+            .zip(entries.iter())
+            .map(|(h, e)| h.join().unwrap_or_else(|_| {
+                to_result(&e.url, Err("Internal error".to_string()))
+            }))
+            .collect()
+    });
+}
+
+fn to_result(server: &str, res: Result<Value, String>) -> ServerResult {
+    return match res {
+        Ok(v) => ServerResult {
+            server: server.to_string(),
+            data: Some(v),
+            error: None,
+        },
+        Err(e) => ServerResult {
+            server: server.to_string(),
+            data: None,
+            error: Some(e),
+        },
+    };
+}
+
+/// The error message from an API response, if it's an error
+fn api_error(v: &Value) -> Option<String> {
+    let e = v.get("error")?;
+    let msg = e["message"].as_str()
+        .or_else(|| e["key"].as_str())
+        .unwrap_or("Unknown error");
+
+    return match e["hint"].as_str() {
+        Some(h) => Some(format!("{} ({})", msg, h)),
+        None => Some(msg.to_string()),
+    };
+}
+
+/// Whether the server rejected the request for a missing or expired session
+fn is_unauthorized(v: &Value) -> bool {
+    return v["error"]["key"].as_str() == Some("unauthorized");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_api_error() {
+        let err = json!({"error": {"key": "unauthorized",
+            "message": "Unauthorized", "hint": null}});
+        assert_eq!(api_error(&err), Some("Unauthorized".to_string()));
+        assert!(is_unauthorized(&err));
+        let err = json!({"error": {"key": "bad_request",
+            "message": "Invalid value", "hint": "timer"}});
+        assert_eq!(api_error(&err), Some("Invalid value (timer)".to_string()));
+        assert!(!is_unauthorized(&err));
+        assert_eq!(api_error(&json!({"blocking": "enabled"})), None);
+    }
+
+    #[test]
+    fn test_unreachable_server() {
+        let sessions = PiSessions::from_cfg(&[
+            PiServer::new("http://127.0.0.1:1", "passwd"),
+        ]);
+        let res = sessions.run(|p| p.summary());
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].server, "http://127.0.0.1:1");
+        assert!(!res[0].is_ok());
+        assert!(res[0].data.is_none());
+    }
+
+    #[test]
+    fn test_run_on() {
+        let sessions = PiSessions::from_cfg(&[
+            PiServer::new("http://127.0.0.1:1", "passwd"),
+            PiServer::new("http://localhost:1/", "passwd"),
+        ]);
+        let targets = vec!["LOCALHOST".to_string()];
+        let res = sessions.run_on(Some(&targets), |p| p.summary()).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].server, "http://localhost:1");
+        let targets = vec!["pihole.local".to_string()];
+        assert!(sessions.run_on(Some(&targets), |p| p.summary()).is_err());
+        assert_eq!(sessions.run_on(None, |p| p.summary()).unwrap().len(), 2);
+    }
+}
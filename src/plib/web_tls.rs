@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+use hyper::net::{HttpStream, NetworkStream, SslServer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use log::{debug, info, warn};
+
+/// How often to check the certificate files for changes
+const RELOAD_CHECK: Duration = Duration::from_secs(60);
+
+/// Terminates TLS for the web server with a certificate and key from PEM
+/// files.  The files are reloaded when they change on disk, so renewals
+/// (ex: by certbot) are picked up without a restart
+#[derive(Clone)]
+pub struct TlsServer {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl TlsServer {
+    pub fn from_files(cert_file: &Path, key_file: &Path) -> Result<Self, String> {
+        let config = load_config(cert_file, key_file)?;
+
+        return Ok(Self {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+        });
+    }
+
+    /// Start a thread that reloads the certificate and key when either of
+    /// the files changes.  If they can't be loaded, ex: when only one has
+    /// been written so far, the old ones are kept until the next check
+    pub fn watch(&self) {
+        let tls = self.clone();
+        let mut loaded = tls.mtimes();
+        thread::spawn(move || loop {
+            thread::sleep(RELOAD_CHECK);
+            let mtimes = tls.mtimes();
+            if mtimes == loaded {
+                continue;
+            }
+
+            match load_config(&tls.cert_file, &tls.key_file) {
+                Ok(c) => {
+                    info!("Reloaded the TLS certificate from {}",
+                        tls.cert_file.display());
+                    *tls.config.write().unwrap() = Arc::new(c);
+                    loaded = mtimes;
+                },
+                Err(e) => warn!("Failed to reload the TLS certificate, \
+                    keeping the old one: {}", e),
+            }
+        });
+    }
+
+    fn mtimes(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let mtime = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+        return (mtime(&self.cert_file), mtime(&self.key_file));
+    }
+}
+
+impl SslServer for TlsServer {
+    type Stream = TlsStream;
+
+    /// The handshake happens on the first read from the connection, in its
+    /// own thread, rather than here in the accept loop
+    fn wrap_server(&self, stream: HttpStream) -> hyper::Result<TlsStream> {
+        let config = self.config.read().unwrap().clone();
+        let conn = ServerConnection::new(config)
+            .map_err(|e| hyper::Error::Ssl(Box::new(e)))?;
+
+        return Ok(TlsStream(Arc::new(Mutex::new(StreamOwned::new(conn, stream)))));
+    }
+}
+
+/// A TLS connection.  Hyper needs to be able to clone the stream, though it
+/// only uses it from one thread at a time
+#[derive(Clone)]
+pub struct TlsStream(Arc<Mutex<StreamOwned<ServerConnection, HttpStream>>>);
+
+impl TlsStream {
+    fn lock(&self) -> MutexGuard<'_, StreamOwned<ServerConnection, HttpStream>> {
+        return match self.0.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.lock().read(buf);
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.lock().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.lock().flush();
+    }
+}
+
+impl NetworkStream for TlsStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        return self.lock().sock.peer_addr();
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return self.lock().sock.set_read_timeout(dur);
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return self.lock().sock.set_write_timeout(dur);
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        let mut guard = self.lock();
+        let s = &mut *guard;
+        s.conn.send_close_notify();
+        // This is only best effort, the other side may already be gone
+        if let Err(e) = s.conn.complete_io(&mut s.sock) {
+            debug!("Failed to send the TLS close notify: {}", e);
+        }
+        return s.sock.close(how);
+    }
+}
+
+fn load_config(cert_file: &Path, key_file: &Path) -> Result<ServerConfig, String> {
+    let open = |p: &Path| File::open(p)
+        .map(BufReader::new)
+        .map_err(|e| format!("Failed to open {}: {}", p.display(), e));
+
+    let certs = rustls_pemfile::certs(&mut open(cert_file)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate in {}: {}",
+            cert_file.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}",
+            cert_file.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_file)?)
+        .map_err(|e| format!("Invalid key in {}: {}", key_file.display(), e))?
+        .ok_or_else(|| format!("No private key found in {}",
+            key_file.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    return ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_errors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let err = TlsServer::from_files(&dir.join("nope.pem"),
+            &dir.join("nope.key")).err().unwrap();
+        assert!(err.starts_with("Failed to open"));
+        // Not a PEM file at all
+        let err = TlsServer::from_files(&dir.join("Cargo.toml"),
+            &dir.join("Cargo.toml")).err().unwrap();
+        assert!(err.starts_with("No certificates found"));
+    }
+}
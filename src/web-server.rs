@@ -1,3 +1,4 @@
+#![allow(clippy::needless_return)]
 extern crate chrono;
 #[macro_use]
 extern crate clap;
@@ -6,6 +7,7 @@ extern crate log;
 extern crate iron;
 extern crate router;
 extern crate configparser;
+extern crate url;
 
 mod plib;
 
@@ -14,27 +16,184 @@ use clap::{ArgMatches, Arg, App};
 use std::{
     path::Path,
     sync::Arc,
-    io::prelude::*,
-    fs::File,
+    io::{self, prelude::*},
+    thread,
+    time::Duration,
 };
 use iron::{
     prelude::*,
     status,
-    modifiers::Header,
-    headers::ContentType,
+    modifiers::{Header, RedirectRaw},
+    headers::{
+        Authorization, Basic, Bearer, ContentLength, ContentType, Cookie,
+        SetCookie,
+    },
     mime::{Mime, TopLevel, SubLevel},
+    method::Method,
+    typemap, AroundMiddleware, Handler,
 };
 use router::Router;
 use plib::{
     config::{self, PiConfig},
-    pihole::Pihole,
+    web_assets::{self, Asset},
+    web_auth::{self, Auth, Identity, Role},
     web_config::get_config,
+    web_events::EventHub,
+    web_sessions::{PiSessions, ServerResult},
+    web_tls::TlsServer,
 };
 use configparser::ini::Ini;
+use serde::Deserialize;
+use serde_json::{json, Value};
 
 struct ReqContext {
     pub web_conf: Ini,
-    pub servers: Vec<Pihole>,
+    pub servers: PiSessions,
+    pub events: Arc<EventHub>,
+    pub auth: Auth,
+    /// Whether we're serving HTTPS, so cookies can be marked as secure
+    pub tls: bool,
+}
+
+/// The read only routes under `/api/v1`, named after the CLI subcommands
+const API_GETS: &[&str] = &["status", "summary", "version", "top-domains",
+    "top-clients", "upstreams", "query-types", "recent-blocked"];
+
+/// The most items the `count` parameter can ask for, so one request can't
+/// have the servers build huge lists
+const MAX_COUNT: usize = 1000;
+
+/// The routes that can be used without logging in
+const PUBLIC_PATHS: &[&str] = &["login", "logout"];
+
+/// How long to hold up the response to a wrong password, to slow down
+/// guessing
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(1);
+
+const LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>pi-ctl login</title>
+</head>
+<body>
+  <h1>pi-ctl</h1>
+  {error}
+  <form method="post" action="/login">
+    <p><label>User <input name="user" autocomplete="username"></label></p>
+    <p><label>Password <input name="passwd" type="password"
+      autocomplete="current-password"></label></p>
+    <p><button type="submit">Log in</button></p>
+  </form>
+</body>
+</html>
+"#;
+
+/// The identity of an authenticated request, stored in its extensions
+struct ReqIdentity;
+
+impl typemap::Key for ReqIdentity {
+    type Value = Identity;
+}
+
+/// Wraps the routes so every request needs HTTP Basic credentials, a bearer
+/// token or a session cookie, except for the login routes
+struct AuthCheck {
+    ctx: Arc<ReqContext>,
+}
+
+struct AuthHandler {
+    ctx: Arc<ReqContext>,
+    handler: Box<dyn Handler>,
+}
+
+impl AroundMiddleware for AuthCheck {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        return Box::new(AuthHandler { ctx: self.ctx, handler });
+    }
+}
+
+impl Handler for AuthHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let auth = &self.ctx.auth;
+        if !auth.enabled {
+            req.extensions.insert::<ReqIdentity>(Identity::Anonymous);
+            return self.handler.handle(req);
+        }
+        let path = req.url.path();
+        if path.len() == 1 && PUBLIC_PATHS.contains(&path[0]) {
+            return self.handler.handle(req);
+        }
+
+        if let Some(id) = identify(req, auth) {
+            if !csrf_ok(req, auth) {
+                warn!("Missing or invalid CSRF token for {}", req.url);
+                return Ok(json_error(status::Forbidden,
+                    "Missing or invalid CSRF token"));
+            }
+            req.extensions.insert::<ReqIdentity>(id);
+            return self.handler.handle(req);
+        }
+
+        // Send browsers without credentials to the login page, and everything
+        // else gets a Basic auth challenge
+        let is_page = path == [""]
+            && req.headers.get::<Authorization<Basic>>().is_none();
+        if is_page {
+            return Ok(redirect("login", None));
+        }
+
+        let mut resp = Response::with((status::Unauthorized, "Unauthorized\n"));
+        resp.headers.set_raw("WWW-Authenticate",
+            vec![b"Basic realm=\"pi-ctl\", charset=\"UTF-8\"".to_vec()]);
+        return Ok(resp);
+    }
+}
+
+/// Return who the request is from, if it has valid credentials
+fn identify(req: &Request, auth: &Auth) -> Option<Identity> {
+    if let Some(Authorization(b)) = req.headers.get::<Authorization<Basic>>() {
+        let passwd = b.password.as_deref().unwrap_or("");
+        if auth.check_password(&b.username, passwd) {
+            return Some(Identity::User(b.username.to_lowercase()));
+        }
+        warn!("Failed login for user '{}'", b.username);
+        thread::sleep(FAILED_LOGIN_DELAY);
+        return None;
+    }
+    if let Some(Authorization(b)) = req.headers.get::<Authorization<Bearer>>() {
+        return auth.check_token(&b.token).map(Identity::Token);
+    }
+
+    return session_id(req)
+        .and_then(|id| auth.session_user(&id))
+        .map(Identity::User);
+}
+
+/// Browsers send the session cookie on their own, even from other sites, so
+/// any change made with one also needs the page's CSRF token in a header
+fn csrf_ok(req: &Request, auth: &Auth) -> bool {
+    if req.method == Method::Get || req.method == Method::Head {
+        return true;
+    }
+    let has_creds = req.headers.get::<Authorization<Basic>>().is_some()
+        || req.headers.get::<Authorization<Bearer>>().is_some();
+    let id = match session_id(req) {
+        Some(id) if !has_creds => id,
+        _ => return true,
+    };
+
+    return req.headers.get_raw(web_auth::CSRF_HEADER)
+        .and_then(|h| h.first())
+        .and_then(|t| std::str::from_utf8(t).ok())
+        .map(|t| auth.check_csrf(&id, t))
+        .unwrap_or(false);
+}
+
+fn session_id(req: &Request) -> Option<String> {
+    return req.headers.get::<Cookie>()
+        .and_then(|Cookie(c)| web_auth::find_cookie(c, web_auth::SESSION_COOKIE));
 }
 
 static LOGGER: GlobalLogger = GlobalLogger;
@@ -86,6 +245,11 @@ fn get_args() -> ArgMatches<'static> {
             .help("The path to the web server config file.  Note this is \
                 different, and separate, from the server list file")
         )
+        .arg(Arg::with_name("hash_password")
+            .long("--hash-password")
+            .help("Read a password from stdin, print its hash for the \
+                [users] section of the web config and exit")
+        )
         .get_matches();
 
     return matches;
@@ -120,79 +284,515 @@ fn disable(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
         },
     };
 
-    for s in &ctx.servers {
-        info!("Disabling pihole on {} for {} secs", s.base_url, secs);
-        s.disable(secs);
+    if let Err(e) = req_role(req, &ctx).check_disable(secs as u64) {
+        return Ok(Response::with((status::Forbidden, format!("{}\n", e))));
     }
 
-    return Ok(Response::with((status::Ok, "OK")));
+    info!("Disabling pihole on all servers for {} secs", secs);
+    let res = ctx.servers.run(|p| p.disable(secs));
+
+    return Ok(results_response(&res));
 }
 
-fn enable(_: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
-    for s in &ctx.servers {
-        info!("Enabling pihole for {}", s.base_url);
-        s.enable();
+fn enable(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    if !req_role(req, &ctx).can_toggle() {
+        return Ok(Response::with((status::Forbidden,
+            "Not allowed to enable blocking\n")));
     }
+    info!("Enabling pihole on all servers");
+    let res = ctx.servers.run(|p| p.enable());
 
-    return Ok(Response::with((status::Ok, "OK")));
+    return Ok(results_response(&res));
 }
 
-fn index(_: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
-    let static_dir = ctx.web_conf.get("main", "static_dir").unwrap();
-    let fname = Path::new(&static_dir).join("index.html");
+/// A plain "OK" if every server succeeded, otherwise a line with the error
+/// for each server that failed.  If none of them worked, it's a 502
+fn results_response(res: &[ServerResult]) -> Response {
+    let failed: Vec<&ServerResult> = res.iter().filter(|r| !r.is_ok()).collect();
+    if failed.is_empty() {
+        return Response::with((status::Ok, "OK"));
+    }
 
-    let mut file = File::open(&fname).unwrap();
-    let mut content = String::new();
-    file.read_to_string(&mut content).unwrap();
+    let body: String = failed.iter()
+        .map(|r| format!("{}: {}\n", r.server,
+            r.error.as_deref().unwrap_or("")))
+        .collect();
+    let code = if failed.len() == res.len() {
+        status::BadGateway
+    } else {
+        status::Ok
+    };
+
+    return Response::with((code, body));
+}
+
+/// Run one of the `API_GETS` on all the servers.  The top-* and
+/// recent-blocked calls take a `count` query parameter, default 10
+fn api_get(
+    req: &mut Request,
+    ctx: Arc<ReqContext>,
+    cmd: &str,
+) -> IronResult<Response> {
+    let count = match count_param(req) {
+        Ok(c) => c,
+        Err(resp) => return Ok(resp),
+    };
+
+    let res = match cmd {
+        "status" => ctx.servers.run(|p| p.blocking()),
+        "summary" => ctx.servers.run(|p| p.summary()),
+        "version" => ctx.servers.run(|p| p.version()),
+        "top-domains" => ctx.servers.run(|p| p.top_items(Some(count))),
+        "top-clients" => ctx.servers.run(|p| p.top_clients(Some(count))),
+        "upstreams" => ctx.servers.run(|p| p.get_upstreams()),
+        "query-types" => ctx.servers.run(|p| p.get_query_types()),
+        "recent-blocked" => ctx.servers.run(|p| p.recent_blocked(count)),
+        _ => return Ok(json_error(status::NotFound, "Unknown API call")),
+    };
+
+    return Ok(json_results(&res));
+}
+
+/// Stream the status of the servers, and changes to it, as server-sent
+/// events.  The servers are polled once for all the clients
+fn api_events(_: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    let stream = match ctx.events.subscribe() {
+        Some(s) => s,
+        None => return Ok(json_error(status::ServiceUnavailable,
+            "Too many event stream clients")),
+    };
+
+    let content_type = Header(ContentType(
+        "text/event-stream".parse::<Mime>().unwrap()
+    ));
+    let mut resp = Response::with((status::Ok, content_type));
+    resp.body = Some(Box::new(stream));
+    resp.headers.set_raw("Cache-Control", vec![b"no-cache".to_vec()]);
+    // Keep proxies, like nginx, from holding on to the events
+    resp.headers.set_raw("X-Accel-Buffering", vec![b"no".to_vec()]);
+
+    return Ok(resp);
+}
+
+/// Everything the dashboard shows for each server in one call: the blocking
+/// state and timer, the summary and the `count` most recently blocked
+/// domains
+fn api_dashboard(
+    req: &mut Request,
+    ctx: Arc<ReqContext>,
+) -> IronResult<Response> {
+    let count = match count_param(req) {
+        Ok(c) => c,
+        Err(resp) => return Ok(resp),
+    };
+    let ok = |v: Option<Value>| v.filter(|x| x.get("error").is_none());
+
+    let res = ctx.servers.run(|p| {
+        let blocking = p.blocking()?;
+        if blocking.get("error").is_some() {
+            // Pass the error on, so an expired session gets renewed
+            return Some(blocking);
+        }
+        Some(json!({
+            "blocking": blocking,
+            "summary": ok(p.summary()),
+            "recent_blocked": ok(p.recent_blocked(count))
+                .map(|r| r["blocked"].clone()),
+        }))
+    });
+
+    return Ok(json_results(&res));
+}
+
+/// The JSON body for the enable and disable calls
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ToggleReq {
+    /// How long to disable for
+    seconds: Option<usize>,
+    /// The base URLs or host names of the servers, all of them if not given
+    servers: Option<Vec<String>>,
+}
+
+fn api_enable(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    if !req_role(req, &ctx).can_toggle() {
+        return Ok(json_error(status::Forbidden,
+            "Not allowed to enable blocking"));
+    }
+    let body = match toggle_body(req) {
+        Ok(b) => b,
+        Err(resp) => return Ok(resp),
+    };
+
+    info!("Enabling pihole on {}", targets_str(&body.servers));
+    return Ok(match ctx.servers.run_on(body.servers.as_deref(), |p| p.enable()) {
+        Ok(res) => json_results(&res),
+        Err(e) => json_error(status::BadRequest, &e),
+    });
+}
+
+fn api_disable(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    let body = match toggle_body(req) {
+        Ok(b) => b,
+        Err(resp) => return Ok(resp),
+    };
+    let secs = match body.seconds {
+        Some(s) => s,
+        None => return Ok(json_error(status::BadRequest,
+            "seconds is required")),
+    };
+    if let Err(e) = req_role(req, &ctx).check_disable(secs as u64) {
+        return Ok(json_error(status::Forbidden, &e));
+    }
+
+    info!("Disabling pihole on {} for {} secs", targets_str(&body.servers),
+        secs);
+    let res = ctx.servers.run_on(body.servers.as_deref(), |p| p.disable(secs));
+    return Ok(match res {
+        Ok(res) => json_results(&res),
+        Err(e) => json_error(status::BadRequest, &e),
+    });
+}
+
+/// Who made the request and what they're allowed to do, for the UI to show
+/// only what works for them
+fn api_me(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    let (user, token) = match req.extensions.get::<ReqIdentity>() {
+        Some(Identity::User(u)) => (Some(u.as_str()), None),
+        Some(Identity::Token(t)) => (None, Some(t.as_str())),
+        _ => (None, None),
+    };
+    let role = req_role(req, &ctx);
+
+    return Ok(json_response(status::Ok, &json!({
+        "user": user,
+        "token": token,
+        "role": role.name(),
+        "can_toggle": role.can_toggle(),
+        "max_disable": role.max_disable(),
+    })));
+}
+
+/// The role of whoever made the request.  Only the public routes have no
+/// identity, and those can't change anything
+fn req_role(req: &Request, ctx: &ReqContext) -> Role {
+    return req.extensions.get::<ReqIdentity>()
+        .map(|id| ctx.auth.role(id))
+        .unwrap_or(Role::Viewer);
+}
+
+/// Read the JSON body of an enable or disable call.  It has to be sent as
+/// JSON, which a form on another site can't do, and an empty body is the
+/// same as `{}`
+fn toggle_body(req: &mut Request) -> Result<ToggleReq, Response> {
+    let is_json = matches!(req.headers.get::<ContentType>(),
+        Some(ContentType(Mime(TopLevel::Application, SubLevel::Json, _))));
+    if !is_json {
+        return Err(json_error(status::UnsupportedMediaType,
+            "The body must be application/json"));
+    }
+
+    let mut body = String::new();
+    if req.body.by_ref().take(64 * 1024).read_to_string(&mut body).is_err() {
+        return Err(json_error(status::BadRequest, "Invalid body"));
+    }
+    if body.trim().is_empty() {
+        return Ok(ToggleReq::default());
+    }
+
+    return serde_json::from_str(&body).map_err(|e| {
+        json_error(status::BadRequest, &format!("Invalid body: {}", e))
+    });
+}
+
+fn targets_str(servers: &Option<Vec<String>>) -> String {
+    return match servers {
+        Some(s) => s.join(", "),
+        None => "all servers".to_string(),
+    };
+}
+
+/// The `count` query parameter, default 10 and at most `MAX_COUNT`
+fn count_param(req: &Request) -> Result<usize, Response> {
+    return match query_param(req, "count").map(|c| c.parse::<usize>()) {
+        None => Ok(10),
+        Some(Ok(n)) if n > 0 => Ok(n.min(MAX_COUNT)),
+        _ => Err(json_error(status::BadRequest,
+            "count must be a positive integer")),
+    };
+}
+
+/// Get a parameter from the query string
+fn query_param(req: &Request, name: &str) -> Option<String> {
+    let query = req.url.query()?;
+    return url::form_urlencoded::parse(query.as_bytes())
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned());
+}
+
+/// The per-server results as JSON.  If every server failed, it's a 502
+fn json_results(res: &[ServerResult]) -> Response {
+    let code = if !res.is_empty() && res.iter().all(|r| !r.is_ok()) {
+        status::BadGateway
+    } else {
+        status::Ok
+    };
+
+    return json_response(code, &json!({ "servers": res }));
+}
+
+fn json_error(code: status::Status, msg: &str) -> Response {
+    return json_response(code, &json!({ "error": msg }));
+}
+
+fn json_response(code: status::Status, body: &Value) -> Response {
     let content_type = Header(
-        ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![]))
+        ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![]))
     );
 
-    return Ok(Response::with((status::Ok, content, content_type)));
+    return Response::with((code, body.to_string(), content_type));
+}
+
+fn index(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    let asset = match load_asset(&ctx, "index.html") {
+        Some(a) => a,
+        None => return Ok(Response::with((status::NotFound, "Not found\n"))),
+    };
+
+    // The page sends this back with any changes.  Since it's different for
+    // each login, the page itself is never cached
+    let csrf = session_id(req)
+        .and_then(|id| ctx.auth.csrf_token(&id))
+        .unwrap_or_default();
+    let content = String::from_utf8_lossy(&asset.body)
+        .replace("{csrf_token}", &csrf);
+    let mut resp = body_response(req, content.into_bytes(), asset.mime);
+    resp.headers.set_raw("Cache-Control", vec![b"no-store".to_vec()]);
+
+    return Ok(resp);
 }
 
 fn static_f(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
-    let static_dir = ctx.web_conf.get("main", "static_dir").unwrap();
-    // Strip off the first part of the path ("static") and replace it with the
-    // static dir to get a proper path to a static file
-    let path = &req.url.path()[1..];
-    let fname = Path::new(&static_dir).join(path.join("/"));
-    info!("serving file: {:?}", fname);
-    if !fname.exists() {
-        return Ok(Response::with((status::NotFound, "Not found\n")));
+    // Strip off the first part of the path ("static") to get the path of the
+    // file under the static dir
+    let path = match web_assets::clean_path(&req.url.path()[1..]) {
+        Some(p) => p,
+        None => return Ok(Response::with((status::NotFound, "Not found\n"))),
+    };
+    debug!("serving file: {}", path);
+    let asset = match load_asset(&ctx, &path) {
+        Some(a) => a,
+        None => return Ok(Response::with((status::NotFound, "Not found\n"))),
+    };
+
+    let fresh = req.headers.get_raw("If-None-Match")
+        .and_then(|h| h.first())
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .map(|v| asset.matches(v))
+        .unwrap_or(false);
+    let mut resp = if fresh {
+        Response::with(status::NotModified)
+    } else {
+        body_response(req, asset.body.into_owned(), asset.mime)
+    };
+    // The URLs don't change between versions, so make browsers check the
+    // ETag each time rather than caching for a while
+    resp.headers.set_raw("ETag", vec![asset.etag.into_bytes()]);
+    resp.headers.set_raw("Cache-Control", vec![b"no-cache".to_vec()]);
+
+    return Ok(resp);
+}
+
+/// A 200 with the body and its type.  A HEAD request gets the same headers,
+/// but no body
+fn body_response(req: &Request, body: Vec<u8>, mime: &str) -> Response {
+    let content_type = Header(ContentType(mime.parse::<Mime>()
+        .unwrap_or(Mime(TopLevel::Application, SubLevel::OctetStream,
+            vec![]))));
+    if req.method != Method::Head {
+        return Response::with((status::Ok, body, content_type));
     }
 
-    let mut content = String::new();
-    File::open(&fname).unwrap().read_to_string(&mut content).unwrap();
+    let mut resp = Response::with((status::Ok, vec![], content_type));
+    resp.headers.set(ContentLength(body.len() as u64));
+    return resp;
+}
+
+/// Get a UI file.  They're built in to the binary, unless `static_dir` is
+/// set, which is handy when working on them
+fn load_asset(ctx: &ReqContext, path: &str) -> Option<Asset> {
+    return match ctx.web_conf.get("main", "static_dir") {
+        Some(d) => web_assets::from_dir(Path::new(&d), path),
+        None => web_assets::embedded(path),
+    };
+}
+
+fn login_page(req: &mut Request) -> IronResult<Response> {
+    let failed = req.url.query().map(|q| q.contains("failed")).unwrap_or(false);
+    let error = if failed { "<p><b>Invalid user or password</b></p>" } else { "" };
+    let content_type = Header(
+        ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![]))
+    );
 
-    return Ok(Response::with((status::Ok, content)));
+    return Ok(Response::with((status::Ok, LOGIN_PAGE.replace("{error}", error),
+        content_type)));
 }
 
-fn create_routes(router: &mut Router, context: Arc<ReqContext>) {
+/// Check the login form and start a session, setting the session cookie
+fn login(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    let mut body = String::new();
+    if req.body.by_ref().take(64 * 1024).read_to_string(&mut body).is_err() {
+        return Ok(Response::with((status::BadRequest, "Invalid form\n")));
+    }
+    let form: Vec<(String, String)> = url::form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect();
+    let field = |name: &str| form.iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str())
+        .unwrap_or("");
+    let user = field("user");
+
+    if !ctx.auth.check_password(user, field("passwd")) {
+        warn!("Failed login for user '{}'", user);
+        thread::sleep(FAILED_LOGIN_DELAY);
+        return Ok(redirect("login?failed=1", None));
+    }
+
+    info!("User '{}' logged in", user);
+    let id = ctx.auth.start_session(user);
+    let mut cookie = format!("{}={}; Path=/; Max-Age={}; HttpOnly; \
+        SameSite=Strict", web_auth::SESSION_COOKIE, id,
+        ctx.auth.session_ttl.as_secs());
+    if ctx.tls {
+        cookie.push_str("; Secure");
+    }
+
+    return Ok(redirect("", Some(cookie)));
+}
+
+fn logout(req: &mut Request, ctx: Arc<ReqContext>) -> IronResult<Response> {
+    if let Some(id) = session_id(req) {
+        ctx.auth.end_session(&id);
+    }
+    let cookie = format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict",
+        web_auth::SESSION_COOKIE);
+
+    return Ok(redirect("login", Some(cookie)));
+}
+
+/// A "303 See Other" to a path on this server, optionally setting a cookie
+fn redirect(path: &str, cookie: Option<String>) -> Response {
+    let mut resp = Response::with((status::SeeOther,
+        RedirectRaw(format!("/{}", path))));
+    if let Some(c) = cookie {
+        resp.headers.set(SetCookie(vec![c]));
+    }
+
+    return resp;
+}
+
+/// All the routes, behind the authentication check
+fn create_chain(context: Arc<ReqContext>, legacy_get_routes: bool) -> Chain {
+    let mut router = Router::new();
+    create_routes(&mut router, context.clone(), legacy_get_routes);
+    let mut chain = Chain::new(router);
+    chain.link_around(AuthCheck { ctx: context });
+
+    return chain;
+}
+
+/// Create the routes.  The old GET routes to enable and disable are only
+/// there with `legacy_get_routes`, since anything that follows links, like
+/// link previews and crawlers, would toggle the blocking
+fn create_routes(
+    router: &mut Router,
+    context: Arc<ReqContext>,
+    legacy_get_routes: bool,
+) {
     // TODO: There's very likely a better way to do this and make the compiler happy
-    let dis_ctx = context.clone();
-    let en_ctx = context.clone();
-    let idx_ctx = context.clone();
-    let static_ctx = context.clone();
+    if legacy_get_routes {
+        let dis_ctx = context.clone();
+        let en_ctx = context.clone();
+        router.get(
+            "/disable/:secs",
+            move |r: &mut Request| disable(r, dis_ctx.clone()),
+            "disable",
+        );
+        router.get(
+            "/enable",
+            move |r: &mut Request| enable(r, en_ctx.clone()),
+            "enable",
+        );
+    }
+    for method in [Method::Get, Method::Head] {
+        let idx_ctx = context.clone();
+        let static_ctx = context.clone();
+        router.route(
+            method.clone(),
+            "/",
+            move |r: &mut Request| index(r, idx_ctx.clone()),
+            format!("index_{}", method),
+        );
+        router.route(
+            method.clone(),
+            "/static/*",
+            move |r: &mut Request| static_f(r, static_ctx.clone()),
+            format!("static_{}", method),
+        );
+    }
+
+    for cmd in API_GETS {
+        let api_ctx = context.clone();
+        router.get(
+            format!("/api/v1/{}", cmd),
+            move |r: &mut Request| api_get(r, api_ctx.clone(), cmd),
+            format!("api_{}", cmd),
+        );
+    }
+    let api_dash_ctx = context.clone();
     router.get(
-        "/disable/:secs",
-        move |r: &mut Request| disable(r, dis_ctx.clone()),
-        "disable",
+        "/api/v1/dashboard",
+        move |r: &mut Request| api_dashboard(r, api_dash_ctx.clone()),
+        "api_dashboard",
     );
+    let api_me_ctx = context.clone();
     router.get(
-        "/enable",
-        move |r: &mut Request| enable(r, en_ctx.clone()),
-        "enable",
+        "/api/v1/me",
+        move |r: &mut Request| api_me(r, api_me_ctx.clone()),
+        "api_me",
     );
+    let api_ev_ctx = context.clone();
     router.get(
-        "/",
-        move |r: &mut Request| index(r, idx_ctx.clone()),
-        "index",
+        "/api/v1/events",
+        move |r: &mut Request| api_events(r, api_ev_ctx.clone()),
+        "api_events",
     );
-    router.get(
-        "/static/*",
-        move |r: &mut Request| static_f(r, static_ctx.clone()),
-        "static",
+    let api_en_ctx = context.clone();
+    let api_dis_ctx = context.clone();
+    router.post(
+        "/api/v1/enable",
+        move |r: &mut Request| api_enable(r, api_en_ctx.clone()),
+        "api_enable",
+    );
+    router.post(
+        "/api/v1/disable",
+        move |r: &mut Request| api_disable(r, api_dis_ctx.clone()),
+        "api_disable",
+    );
+
+    let login_ctx = context.clone();
+    let logout_ctx = context.clone();
+    router.get("/login", login_page, "login_page");
+    router.post(
+        "/login",
+        move |r: &mut Request| login(r, login_ctx.clone()),
+        "login",
+    );
+    router.post(
+        "/logout",
+        move |r: &mut Request| logout(r, logout_ctx.clone()),
+        "logout",
     );
 }
 
@@ -200,6 +800,22 @@ fn main() {
     let args = get_args();
     setup_logging(&args);
 
+    if args.is_present("hash_password") {
+        let mut passwd = String::new();
+        if let Err(e) = io::stdin().read_line(&mut passwd) {
+            error!("Failed to read the password: {}", e);
+            std::process::exit(1);
+        }
+        match web_auth::hash_password(passwd.trim_end_matches(['\r', '\n'])) {
+            Ok(h) => println!("{}", h),
+            Err(e) => {
+                error!("Failed to hash the password: {}", e);
+                std::process::exit(1);
+            },
+        }
+        std::process::exit(0);
+    }
+
     let conf_path = Path::new(args.value_of("pi_list_config").unwrap());
     let server_conf = match PiConfig::from_path(conf_path) {
         Ok(c) => c,
@@ -214,20 +830,192 @@ fn main() {
     };
 
     let web_conf = get_config(args.value_of("web_config").unwrap());
+    let auth = match Auth::from_ini(&web_conf) {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Invalid auth config: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let legacy_get_routes = match web_conf.getbool("main", "legacy_get_routes") {
+        Ok(v) => v.unwrap_or(false),
+        Err(e) => {
+            error!("Invalid legacy_get_routes setting: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let uint_setting = |name: &str, default: u64| {
+        match web_conf.getuint("main", name) {
+            Ok(v) => v.unwrap_or(default),
+            Err(e) => {
+                error!("Invalid {} setting: {}", name, e);
+                std::process::exit(1);
+            },
+        }
+    };
+    let events_interval = uint_setting("events_interval", 5).max(1);
+    let max_event_clients = uint_setting("max_event_clients", 16) as usize;
+    if legacy_get_routes {
+        warn!("The legacy GET routes to enable and disable are turned on");
+    }
+    if !auth.enabled {
+        warn!("Authentication is disabled, anyone who can reach this server \
+            can control your Pi-holes");
+    }
+
+    let tls = match (web_conf.get("main", "cert_file"),
+            web_conf.get("main", "key_file")) {
+        (Some(cert), Some(key)) => {
+            match TlsServer::from_files(Path::new(&cert), Path::new(&key)) {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    error!("Failed to load the TLS certificate: {}", e);
+                    std::process::exit(1);
+                },
+            }
+        },
+        (None, None) => None,
+        _ => {
+            error!("Both cert_file and key_file need to be set for HTTPS");
+            std::process::exit(1);
+        },
+    };
 
     let context = Arc::new(ReqContext {
-        web_conf: web_conf,
-        servers: server_conf.servers
-            .iter()
-            .map(|x| Pihole::from_cfg(x))
-            .collect(),
+        web_conf,
+        servers: PiSessions::from_cfg(&server_conf.servers),
+        events: EventHub::new(Duration::from_secs(events_interval),
+            max_event_clients),
+        auth,
+        tls: tls.is_some(),
     });
+    let poll_ctx = context.clone();
+    context.events.start(move || poll_ctx.servers.run(|p| {
+        let blocking = p.blocking()?;
+        if blocking.get("error").is_some() {
+            return Some(blocking);
+        }
+        let summary = p.summary().filter(|x| x.get("error").is_none());
+        Some(json!({ "blocking": blocking, "summary": summary }))
+    }));
 
-    let mut router = Router::new();
-    create_routes(&mut router, context.clone());
+    let chain = create_chain(context.clone(), legacy_get_routes);
+
+    // Each event stream keeps a thread busy for as long as it's open, so add
+    // those on top of the ones for regular requests
+    let mut iron = Iron::new(chain);
+    iron.threads += max_event_clients;
 
-    debug!("Creating web server bound to {}",
-        context.web_conf.get("main", "bind_to").unwrap());
-    Iron::new(router).http(context.web_conf.get("main", "bind_to").unwrap())
-        .unwrap();
-}
\ No newline at end of file
+    let bind_to = context.web_conf.get("main", "bind_to").unwrap();
+    let res = match tls {
+        Some(t) => {
+            debug!("Creating HTTPS web server bound to {}", bind_to);
+            t.watch();
+            iron.https(bind_to.as_str(), t)
+        },
+        None => {
+            debug!("Creating web server bound to {}", bind_to);
+            iron.http(bind_to.as_str())
+        },
+    };
+    if let Err(e) = res {
+        error!("Failed to start the web server on {}: {}", bind_to, e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use isahc::ReadResponseExt;
+    use plib::config::PiServer;
+
+    /// Serve a handler on a free port and return its base URL.  The server
+    /// is left running, since iron would wait for it to stop on drop
+    fn serve<H: Handler>(handler: H) -> String {
+        let listening = Iron::new(handler).http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listening.socket);
+        std::mem::forget(listening);
+
+        return url;
+    }
+
+    /// A Pi-hole that answers the few calls the tests need.  The top
+    /// domains echo back the count they were asked for
+    fn fake_pihole() -> String {
+        return serve(|req: &mut Request| {
+            let path = req.url.path().join("/");
+            let body = match (&req.method, path.as_str()) {
+                (Method::Post, "api/auth") => json!({"session": {
+                    "valid": true, "sid": "abc", "validity": 300}}),
+                (Method::Delete, "api/auth") =>
+                    return Ok(Response::with(status::NoContent)),
+                (Method::Get, "api/dns/blocking") =>
+                    json!({"blocking": "enabled", "timer": null}),
+                (Method::Get, "api/stats/top_domains") => json!({
+                    "domains": [],
+                    "count": query_param(req, "count"),
+                }),
+                _ => return Ok(json_error(status::NotFound, "Not found")),
+            };
+            Ok(json_response(status::Ok, &body))
+        });
+    }
+
+    fn web_server(servers: &[String]) -> String {
+        let mut web_conf = Ini::new();
+        web_conf.read("[auth]\nenabled = false\n".to_string()).unwrap();
+        let servers: Vec<PiServer> = servers.iter()
+            .map(|s| PiServer::new(s, "passwd"))
+            .collect();
+        let context = Arc::new(ReqContext {
+            auth: Auth::from_ini(&web_conf).unwrap(),
+            web_conf,
+            servers: PiSessions::from_cfg(&servers),
+            events: EventHub::new(Duration::from_secs(1), 1),
+            tls: false,
+        });
+
+        return serve(create_chain(context, false));
+    }
+
+    fn get(url: &str) -> (u16, Value) {
+        let mut resp = isahc::get(url).unwrap();
+        let body = serde_json::from_str(&resp.text().unwrap()).unwrap();
+
+        return (resp.status().as_u16(), body);
+    }
+
+    #[test]
+    fn test_api_results() {
+        let pihole = fake_pihole();
+        let down = "http://127.0.0.1:1".to_string();
+        let web = web_server(&[pihole.clone(), down.clone()]);
+
+        let (code, body) = get(&format!("{}/api/v1/status", web));
+        assert_eq!(code, 200);
+        assert_eq!(body["servers"][0], json!({
+            "server": pihole,
+            "data": {"blocking": "enabled", "timer": null},
+        }));
+        assert_eq!(body["servers"][1]["server"], json!(down));
+        assert!(body["servers"][1]["error"].is_string());
+        assert!(body["servers"][1].get("data").is_none());
+
+        let (_, body) = get(&format!("{}/api/v1/top-domains?count=5000", web));
+        assert_eq!(body["servers"][0]["data"]["count"], json!("1000"));
+        let (code, body) = get(&format!("{}/api/v1/top-domains?count=0", web));
+        assert_eq!(code, 400);
+        assert_eq!(body["error"], json!("count must be a positive integer"));
+    }
+
+    #[test]
+    fn test_api_all_failed() {
+        let web = web_server(&["http://127.0.0.1:1".to_string()]);
+
+        let (code, body) = get(&format!("{}/api/v1/summary", web));
+        assert_eq!(code, 502);
+        assert_eq!(body["servers"].as_array().unwrap().len(), 1);
+        assert!(body["servers"][0]["error"].is_string());
+    }
+}
@@ -1,25 +1,339 @@
 var MPI = {
-  enable: function () {
-    $.ajax({
-      url: 'https://pi-ctl.splitstreams.com/enable',
-      error: function(xhr, stat, err) {
-        alert('An error occured (' + stat + ') enabling piholes: ' + err);
-      },
-      success: function(data, stat, xhr) {
-        alert('Successfully enabled the pihole servers');
+  // The disable durations offered as buttons, in seconds
+  presets: [30, 300, 900, 3600],
+  // How often to reload the data from the servers.  The blocking state and
+  // stats come from the event stream in between, when it's working
+  refreshMs: 10000,
+  streamRefreshMs: 60000,
+  refreshTimer: null,
+  // When each disabled server turns back on, by server URL
+  deadlines: {},
+  // Who's logged in and what they can do, from /api/v1/me
+  me: null,
+
+  init: function () {
+    var presets = document.getElementById('presets');
+    MPI.presets.forEach(function (secs) {
+      var b = document.createElement('button');
+      b.textContent = MPI.fmtDuration(secs);
+      b.dataset.action = 'disable';
+      b.dataset.secs = secs;
+      presets.appendChild(b);
+    });
+
+    document.body.addEventListener('click', MPI.onClick);
+    MPI.loadMe();
+    MPI.refresh();
+    MPI.setRefresh(MPI.refreshMs);
+    setInterval(MPI.tick, 1000);
+    MPI.listen();
+  },
+
+  loadMe: function () {
+    fetch('/api/v1/me', {credentials: 'same-origin'})
+      .then(MPI.readJson)
+      .then(function (me) {
+        MPI.me = me;
+        var who = me.user || me.token;
+        var desc = me.role;
+        if (me.max_disable) {
+          desc += ', disable for up to ' + MPI.fmtDuration(me.max_disable);
+        }
+        document.getElementById('whoami').textContent =
+          who ? who + ' (' + desc + ')' : '';
+        if (me.max_disable) {
+          document.getElementById('dis_secs').max = me.max_disable;
+        }
+        MPI.applyRole();
+      })
+      .catch(function (err) {
+        MPI.showMessage('Failed to load your role: ' + err.message);
+      });
+  },
+
+  // Only show the controls the role can use
+  applyRole: function () {
+    var me = MPI.me;
+    if (!me) {
+      return;
+    }
+    document.body.classList.toggle('read-only', !me.can_toggle);
+    document.querySelectorAll('button[data-secs]').forEach(function (b) {
+      b.hidden = !!me.max_disable && parseInt(b.dataset.secs, 10) > me.max_disable;
+    });
+  },
+
+  setRefresh: function (ms) {
+    clearInterval(MPI.refreshTimer);
+    MPI.refreshTimer = setInterval(MPI.refresh, ms);
+  },
+
+  // Follow the live status from the server, falling back to polling if the
+  // stream isn't available
+  listen: function () {
+    if (!window.EventSource) {
+      return;
+    }
+    var es = new EventSource('/api/v1/events');
+    es.onopen = function () {
+      MPI.setRefresh(MPI.streamRefreshMs);
+    };
+    es.onerror = function () {
+      MPI.setRefresh(MPI.refreshMs);
+    };
+    es.addEventListener('status', function (ev) {
+      MPI.applyStatus(JSON.parse(ev.data).servers || []);
+    });
+    es.addEventListener('stats', function (ev) {
+      MPI.applyStats(JSON.parse(ev.data).servers || []);
+    });
+    es.addEventListener('change', function (ev) {
+      var c = JSON.parse(ev.data);
+      // A server that comes back may have new recently blocked domains
+      if (c.change === 'reachable') {
+        MPI.refresh();
+      }
+    });
+  },
+
+  // Update the cards from a status event
+  applyStatus: function (servers) {
+    var deadlines = {};
+    servers.forEach(function (st) {
+      var card = MPI.findCard(st.server);
+      if (!card) {
+        return;
+      }
+      card.classList.remove('enabled', 'disabled', 'failed');
+      if (!st.reachable) {
+        card.classList.add('failed');
+        delete card.dataset.state;
+        card.querySelector('.state').textContent = 'Error: ' + st.error;
+        return;
+      }
+      card.classList.add(st.blocking);
+      card.dataset.state = st.blocking;
+      if (st.timer) {
+        deadlines[st.server] = Date.now() + st.timer * 1000;
+      }
+      var dl = card.querySelector('.stats');
+      dl.textContent = '';
+      MPI.renderStats(dl, st.summary);
+    });
+
+    MPI.deadlines = deadlines;
+    MPI.tick();
+  },
+
+  // Update only the summary stats on the cards, from a stats event
+  applyStats: function (servers) {
+    servers.forEach(function (st) {
+      var card = MPI.findCard(st.server);
+      if (!card || !st.summary) {
+        return;
       }
+      var dl = card.querySelector('.stats');
+      dl.textContent = '';
+      MPI.renderStats(dl, st.summary);
     });
   },
-  disable: function (seconds) {
-    $.ajax({
-      url: 'https://pi-ctl.splitstreams.com/disable/' + seconds,
-      error: function(xhr, stat, err) {
-        alert('An error occured (' + stat + ') disabling piholes: ' + err);
+
+  findCard: function (server) {
+    var cards = document.querySelectorAll('.server');
+    for (var i = 0; i < cards.length; i++) {
+      if (cards[i].dataset.server === server) {
+        return cards[i];
+      }
+    }
+    return null;
+  },
+
+  onClick: function (ev) {
+    var b = ev.target.closest('button[data-action]');
+    if (!b) {
+      return;
+    }
+    var card = b.closest('.server');
+    var servers = card ? [card.dataset.server] : undefined;
+
+    switch (b.dataset.action) {
+      case 'enable':
+        MPI.post('/api/v1/enable', {servers: servers});
+        break;
+      case 'disable':
+        MPI.post('/api/v1/disable',
+          {seconds: parseInt(b.dataset.secs, 10), servers: servers});
+        break;
+      case 'disable-custom':
+        var secs = parseInt(document.getElementById('dis_secs').value, 10);
+        if (!(secs > 0)) {
+          MPI.showMessage('Enter a number of seconds to disable for');
+          return;
+        }
+        if (MPI.me && MPI.me.max_disable && secs > MPI.me.max_disable) {
+          MPI.showMessage('You can only disable blocking for up to ' +
+            MPI.fmtDuration(MPI.me.max_disable));
+          return;
+        }
+        MPI.post('/api/v1/disable', {seconds: secs});
+        break;
+    }
+  },
+
+  // Changes are POSTed as JSON with the CSRF token from the page
+  post: function (path, body) {
+    fetch(path, {
+      method: 'POST',
+      credentials: 'same-origin',
+      headers: {
+        'Content-Type': 'application/json',
+        'X-CSRF-Token':
+          document.querySelector('meta[name="csrf-token"]').content
       },
-      success: function(data, stat, xhr) {
-        alert('Successfully disabled the pihole servers for ' + seconds +
-          ' seconds');
+      body: JSON.stringify(body)
+    })
+      .then(MPI.readJson)
+      .then(function (data) {
+        MPI.showErrors(data.servers || []);
+        MPI.refresh();
+      })
+      .catch(function (err) {
+        MPI.showMessage(err.message);
+      });
+  },
+
+  refresh: function () {
+    fetch('/api/v1/dashboard', {credentials: 'same-origin'})
+      .then(MPI.readJson)
+      .then(function (data) {
+        MPI.render(data.servers || []);
+      })
+      .catch(function (err) {
+        MPI.showMessage('Failed to load the servers: ' + err.message);
+      });
+  },
+
+  // Parse a JSON response, turning an error status into an exception.  A
+  // 502 still has the per-server results in it
+  readJson: function (resp) {
+    if (resp.status === 401) {
+      window.location = '/login';
+      throw new Error('Not logged in');
+    }
+    return resp.json().then(function (data) {
+      if (!resp.ok && !data.servers) {
+        throw new Error(data.error || resp.statusText);
+      }
+      return data;
+    });
+  },
+
+  render: function (results) {
+    var list = document.getElementById('servers');
+    var tpl = document.getElementById('server-tpl');
+    var deadlines = {};
+    list.textContent = '';
+
+    results.forEach(function (r) {
+      var card = tpl.content.firstElementChild.cloneNode(true);
+      card.dataset.server = r.server;
+      card.querySelector('.name').textContent = r.server;
+
+      if (r.error) {
+        card.classList.add('failed');
+        card.querySelector('.state').textContent = 'Error: ' + r.error;
+        list.appendChild(card);
+        return;
+      }
+
+      var b = r.data.blocking;
+      card.classList.add(b.blocking);
+      card.dataset.state = b.blocking;
+      if (b.timer) {
+        deadlines[r.server] = Date.now() + b.timer * 1000;
       }
+      MPI.renderStats(card.querySelector('.stats'), r.data.summary);
+      var blocked = card.querySelector('.blocked');
+      (r.data.recent_blocked || []).forEach(function (d) {
+        var li = document.createElement('li');
+        li.textContent = d;
+        blocked.appendChild(li);
+      });
+      list.appendChild(card);
     });
+
+    MPI.deadlines = deadlines;
+    MPI.applyRole();
+    MPI.tick();
+  },
+
+  renderStats: function (dl, s) {
+    if (!s || !s.queries) {
+      return;
+    }
+    var stats = [
+      ['Queries', s.queries.total],
+      ['Blocked', s.queries.blocked],
+      ['Blocked %', s.queries.percent_blocked.toFixed(1)],
+      ['Active clients', s.clients.active],
+      ['Domains on lists', s.gravity.domains_being_blocked]
+    ];
+    stats.forEach(function (st) {
+      var dt = document.createElement('dt');
+      var dd = document.createElement('dd');
+      dt.textContent = st[0];
+      dd.textContent = typeof st[1] === 'number' ?
+        st[1].toLocaleString() : st[1];
+      dl.appendChild(dt);
+      dl.appendChild(dd);
+    });
+  },
+
+  // Update the blocking state, with the countdown for disabled servers
+  tick: function () {
+    document.querySelectorAll('.server[data-state]').forEach(function (card) {
+      var state = card.querySelector('.state');
+      var deadline = MPI.deadlines[card.dataset.server];
+      if (card.dataset.state !== 'disabled') {
+        state.textContent = 'Blocking ' + card.dataset.state;
+      } else if (deadline) {
+        var left = Math.max(0, Math.round((deadline - Date.now()) / 1000));
+        state.textContent = 'Blocking disabled, enabled again in ' +
+          MPI.fmtDuration(left);
+      } else {
+        state.textContent = 'Blocking disabled';
+      }
+    });
+  },
+
+  showErrors: function (results) {
+    var failed = results.filter(function (r) { return r.error; });
+    if (failed.length) {
+      MPI.showMessage(failed.map(function (r) {
+        return r.server + ': ' + r.error;
+      }).join('\n'));
+    } else {
+      document.getElementById('message').hidden = true;
+    }
+  },
+
+  showMessage: function (msg) {
+    var el = document.getElementById('message');
+    el.textContent = msg;
+    el.hidden = false;
+  },
+
+  // Format seconds as ex: "1h 5m" or "30s"
+  fmtDuration: function (secs) {
+    var h = Math.floor(secs / 3600);
+    var m = Math.floor((secs % 3600) / 60);
+    var s = secs % 60;
+    var parts = [];
+    if (h) { parts.push(h + 'h'); }
+    if (m) { parts.push(m + 'm'); }
+    if (s || !parts.length) { parts.push(s + 's'); }
+    return parts.join(' ');
   }
 };
+
+document.addEventListener('DOMContentLoaded', MPI.init);